@@ -0,0 +1,27 @@
+#define DEPTH disabled
+#define CULL disabled
+#define SHAPE filled_triangles
+
+#define VERTEX_LOCAL_POSITION
+
+layout(location = 0) out vec4 out_color;
+
+void fragment() {
+    uint canvas = uint(material.a.x);
+    vec2 size = material.a.yz;
+    float threshold = material.b.x;
+    float strength = material.b.y;
+    float radius = material.b.z;
+
+    // blur only the bright parts of the image
+    vec3 glow = vec3(0.0);
+    for (int x = -3; x <= 3; x++) {
+        for (int y = -3; y <= 3; y++) {
+            vec3 c = tex(canvas, in_uv + vec2(x, y) * radius / size).rgb;
+            glow += max(c - threshold, 0.0);
+        }
+    }
+
+    vec4 color = tex(canvas, in_uv);
+    out_color = vec4(color.rgb + glow / 49.0 * strength, color.a);
+}
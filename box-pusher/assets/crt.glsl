@@ -10,7 +10,7 @@ float PI = 3.14159265359;
 
 vec2 curve_uv(vec2 uv, vec2 curvature) {
     uv = uv * 2.0 - 1.0;
-    vec2 offset = abs(uv.yx) / curvature;
+    vec2 offset = abs(uv.yx) * curvature;
     uv = uv + uv * offset * offset;
     uv = uv * 0.5 + 0.5;
     return uv;
@@ -19,23 +19,44 @@ vec2 curve_uv(vec2 uv, vec2 curvature) {
 void fragment() {
     // remap the uvs
     vec2 uv = curve_uv(in_uv, material.b.xy);
-    float black = material.c.x;
-    vec2 size = material.d.xy;
+    uint canvas = uint(material.a.x);
+    float scanlines = material.c.x;
+    float vignette_strength = material.c.y;
+    float bloom = material.c.z;
+    float aberration = material.c.w;
+    vec2 size = material.a.yz;
 
     if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
         // output black when out of bounds
         out_color = vec4(vec3(0.0), 1.0);
     } else {
+        // split the color channels apart
+        vec2 shift = vec2(aberration / size.x, 0.0);
+        vec3 color = vec3(
+            tex(canvas, uv + shift).r,
+            tex(canvas, uv).g,
+            tex(canvas, uv - shift).b
+        );
+
+        // add a glow from the surrounding pixels
+        vec3 glow = vec3(0.0);
+        for (int x = -2; x <= 2; x++) {
+            for (int y = -2; y <= 2; y++) {
+                glow += tex(canvas, uv + vec2(x, y) * 1.5 / size).rgb;
+            }
+        }
+        color += glow / 25.0 * bloom;
+
         // calculate scanlines
         float x_scan = sin((uv.x + 0.005) * size.x * PI * 2.0) * 0.5 + 0.5;
         float y_scan = sin((uv.y + 0.005) * size.y * PI * 2.0) * 0.5 + 0.5;
-        float scan = smoothstep(0.05, 0.3, x_scan * y_scan) * (1.0 - black) + black;
+        float scan = smoothstep(0.05, 0.3, x_scan * y_scan) * scanlines + (1.0 - scanlines);
 
         // calculate vignette
         float x_vignette = clamp(1.0 - pow(in_uv.x * 2.0 - 1.0, 4.0), 0.0, 1.0);
         float y_vignette = clamp(1.0 - pow(in_uv.y * 2.0 - 1.0, 4.0), 0.0, 1.0);
-        float vignette = x_vignette * y_vignette;
+        float vignette = mix(1.0, x_vignette * y_vignette, vignette_strength);
 
-        out_color = tex(uint(material.a.x), uv) * vec4(vec3(scan * vignette), 1.0);
+        out_color = vec4(color * scan * vignette, 1.0);
     }
 }
@@ -0,0 +1,16 @@
+#define DEPTH disabled
+#define CULL disabled
+#define SHAPE filled_triangles
+
+#define VERTEX_LOCAL_POSITION
+
+layout(location = 0) out vec4 out_color;
+
+void fragment() {
+    uint canvas = uint(material.a.x);
+    float levels = max(material.b.x, 2.0) - 1.0;
+
+    // snap every channel to a few levels
+    vec4 color = tex(canvas, in_uv);
+    out_color = vec4(floor(color.rgb * levels + 0.5) / levels, color.a);
+}
@@ -0,0 +1,23 @@
+#define DEPTH disabled
+#define CULL disabled
+#define SHAPE filled_triangles
+
+#define VERTEX_LOCAL_POSITION
+
+layout(location = 0) out vec4 out_color;
+
+void fragment() {
+    uint canvas = uint(material.a.x);
+    vec2 offset = material.b.xy;
+    vec2 size = material.b.zw;
+
+    // map the window onto the viewport
+    vec2 uv = (in_uv - offset) / size;
+
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        // letterbox
+        out_color = vec4(vec3(0.0), 1.0);
+    } else {
+        out_color = vec4(tex(canvas, uv).rgb, 1.0);
+    }
+}
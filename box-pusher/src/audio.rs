@@ -0,0 +1,562 @@
+use kira::instance::InstanceSettings;
+use kira::manager::AudioManager;
+use kira::mixer::SubTrackId;
+use kira::mixer::TrackSettings;
+use kira::parameter::ParameterId;
+use kira::sound::SoundId;
+use kira::Tween;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::music::Music;
+use crate::random::Rng;
+
+/// Everything the game asks of the sound device. The kira backend plays
+/// for real, the null backend only records what would have played.
+pub trait Audio {
+    /// Loads sound effects and which gameplay events play them.
+    fn load_sounds(&mut self, path: &Path) -> Result<()>;
+
+    /// Loads the music tracks levels can ask for.
+    fn load_music(&mut self, path: &Path) -> Result<()>;
+
+    fn event_sound(&self, event: &str) -> Option<&str>;
+
+    fn play_sound(&mut self, name: &str) -> Result<()>;
+
+    /// Switches to a music track, or to silence with `None`.
+    fn play_music(&mut self, name: Option<&str>) -> Result<()>;
+
+    /// Muffles the music while the game is paused.
+    fn set_paused(&mut self, paused: bool);
+
+    fn set_volume(&mut self, volume: Volume, muted: bool) -> Result<()>;
+
+    fn update(&mut self) -> Result<()>;
+
+    /// What the backend was asked to do so far, if it keeps track.
+    #[cfg(test)]
+    fn recorded(&self) -> &[AudioEvent] {
+        &[]
+    }
+}
+
+/// Something the null backend was asked to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioEvent {
+    Sound(String),
+    Music(Option<String>),
+    Volume(Volume, bool),
+}
+
+impl fmt::Display for AudioEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sound(name) => write!(f, "sound {}", name),
+            Self::Music(Some(name)) => write!(f, "music {}", name),
+            Self::Music(None) => write!(f, "music none"),
+            Self::Volume(v, muted) => write!(
+                f,
+                "volume {} {} {}{}",
+                v.master,
+                v.music,
+                v.sfx,
+                if *muted { " muted" } else { "" }
+            ),
+        }
+    }
+}
+
+/// Opens the sound device, falling back to the null backend when audio
+/// is turned off or there is no device. A log file also selects the null
+/// backend so runs can be checked for the sounds they would have made.
+pub fn open(mute: bool, log: Option<&Path>) -> Result<Box<dyn Audio>> {
+    if let Some(path) = log {
+        return Ok(Box::new(NullAudio::with_log(path)?));
+    }
+    if mute {
+        return Ok(Box::new(NullAudio::default()));
+    }
+
+    match KiraAudio::new() {
+        Ok(audio) => Ok(Box::new(audio)),
+        Err(err) => {
+            println!("playing without audio: {}", err);
+            Ok(Box::new(NullAudio::default()))
+        }
+    }
+}
+
+pub struct KiraAudio {
+    manager: AudioManager,
+    buses: Buses,
+    sounds: SoundBank,
+    music: Option<Music>,
+}
+
+impl KiraAudio {
+    pub fn new() -> Result<Self> {
+        let mut manager = AudioManager::new(Default::default())?;
+        let buses = Buses::new(&mut manager)?;
+
+        Ok(Self {
+            manager,
+            buses,
+            sounds: SoundBank::default(),
+            music: None,
+        })
+    }
+}
+
+impl Audio for KiraAudio {
+    fn load_sounds(&mut self, path: &Path) -> Result<()> {
+        self.sounds = SoundBank::load(&mut self.manager, path, self.buses.sfx)?;
+        Ok(())
+    }
+
+    fn load_music(&mut self, path: &Path) -> Result<()> {
+        self.music = Some(Music::load(&mut self.manager, path, self.buses.music)?);
+        Ok(())
+    }
+
+    fn event_sound(&self, event: &str) -> Option<&str> {
+        self.sounds.event_sound(event)
+    }
+
+    fn play_sound(&mut self, name: &str) -> Result<()> {
+        let end = self.sounds.play(&mut self.manager, name)?;
+        if let (Some(music), Some(end)) = (&mut self.music, end) {
+            music.duck(end);
+        }
+        Ok(())
+    }
+
+    fn play_music(&mut self, name: Option<&str>) -> Result<()> {
+        match &mut self.music {
+            Some(music) => music.play(&mut self.manager, name),
+            None => Ok(()),
+        }
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        if let Some(music) = &mut self.music {
+            music.set_paused(paused);
+        }
+    }
+
+    fn set_volume(&mut self, volume: Volume, muted: bool) -> Result<()> {
+        self.buses.set_volume(&mut self.manager, &volume, muted)
+    }
+
+    fn update(&mut self) -> Result<()> {
+        match &mut self.music {
+            Some(music) => music.update(&mut self.manager),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Backend without a sound device. It reads the same files as the kira
+/// backend, so mistakes in them still show up, and can write every sound,
+/// track change and volume change it is asked for to a log, one per line.
+#[derive(Default)]
+pub struct NullAudio {
+    events: HashMap<String, String>,
+    sounds: HashSet<String>,
+    tracks: HashSet<String>,
+    music: Option<String>,
+    log: Option<File>,
+    recorded: Vec<AudioEvent>,
+}
+
+impl NullAudio {
+    pub fn with_log(path: &Path) -> Result<Self> {
+        Ok(Self {
+            log: Some(File::create(path)?),
+            ..Default::default()
+        })
+    }
+
+    fn record(&mut self, event: AudioEvent) -> Result<()> {
+        if let Some(log) = &mut self.log {
+            writeln!(log, "{}", event)?;
+        }
+        self.recorded.push(event);
+        Ok(())
+    }
+}
+
+impl Audio for NullAudio {
+    fn load_sounds(&mut self, path: &Path) -> Result<()> {
+        let json = read_json(path)?;
+        self.sounds = match &json["sounds"] {
+            Value::Object(map) => map.keys().cloned().collect(),
+            _ => return Err(Error::Audio("sounds are not present".to_string())),
+        };
+        self.events = event_sounds(&json)?;
+        Ok(())
+    }
+
+    fn load_music(&mut self, path: &Path) -> Result<()> {
+        let json = read_json(path)?;
+        self.tracks = match &json["tracks"] {
+            Value::Object(map) => map.keys().cloned().collect(),
+            _ => return Err(Error::Audio("tracks are not present".to_string())),
+        };
+        Ok(())
+    }
+
+    fn event_sound(&self, event: &str) -> Option<&str> {
+        self.events.get(event).map(|s| s.as_str())
+    }
+
+    fn play_sound(&mut self, name: &str) -> Result<()> {
+        if !self.sounds.contains(name) {
+            return Err(Error::Audio("unknown sound".to_string()));
+        }
+        self.record(AudioEvent::Sound(name.to_string()))
+    }
+
+    fn play_music(&mut self, name: Option<&str>) -> Result<()> {
+        if self.music.as_deref() == name {
+            return Ok(());
+        }
+        if let Some(name) = name {
+            if !self.tracks.contains(name) {
+                return Err(Error::Audio(format!("unknown music track {}", name)));
+            }
+        }
+        self.music = name.map(|n| n.to_string());
+        self.record(AudioEvent::Music(self.music.clone()))
+    }
+
+    fn set_paused(&mut self, _: bool) {}
+
+    fn set_volume(&mut self, volume: Volume, muted: bool) -> Result<()> {
+        self.record(AudioEvent::Volume(volume, muted))
+    }
+
+    fn update(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn recorded(&self) -> &[AudioEvent] {
+        &self.recorded
+    }
+}
+
+/// Master, music and sound effect volumes from 0 to 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Volume {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            music: 0.8,
+            sfx: 1.0,
+        }
+    }
+}
+
+impl Volume {
+    pub fn from_json(json: &Value) -> Self {
+        let defaults = Self::default();
+        let number = |name: &str, default: f32| match &json[name] {
+            Value::Number(n) => n
+                .as_f64()
+                .map(|n| (n as f32).clamp(0.0, 1.0))
+                .unwrap_or(default),
+            _ => default,
+        };
+
+        Self {
+            master: number("master", defaults.master),
+            music: number("music", defaults.music),
+            sfx: number("sfx", defaults.sfx),
+        }
+    }
+
+    pub fn to_json(self) -> Value {
+        json!({
+            "master": self.master,
+            "music": self.music,
+            "sfx": self.sfx,
+        })
+    }
+}
+
+/// Mixer sub-tracks that music and sound effects play through. Music
+/// and sfx feed into master, so volumes multiply.
+pub struct Buses {
+    pub music: SubTrackId,
+    pub sfx: SubTrackId,
+    // master, music and sfx volume
+    volumes: [ParameterId; 3],
+}
+
+impl Buses {
+    pub fn new(audio: &mut AudioManager) -> Result<Self> {
+        let volumes = [
+            audio.add_parameter(1.0)?,
+            audio.add_parameter(1.0)?,
+            audio.add_parameter(1.0)?,
+        ];
+        let master = audio.add_sub_track(TrackSettings::new().volume(volumes[0]))?;
+        let music = audio.add_sub_track(TrackSettings::new().volume(volumes[1]).parent(master))?;
+        let sfx = audio.add_sub_track(TrackSettings::new().volume(volumes[2]).parent(master))?;
+
+        Ok(Self {
+            music,
+            sfx,
+            volumes,
+        })
+    }
+
+    pub fn set_volume(&self, audio: &mut AudioManager, volume: &Volume, muted: bool) -> Result<()> {
+        let master = if muted { 0.0 } else { volume.master };
+        let levels = [master, volume.music, volume.sfx];
+        for (id, level) in self.volumes.iter().zip(levels.iter()) {
+            audio.set_parameter(*id, *level as f64, Tween::from(0.1))?;
+        }
+        Ok(())
+    }
+}
+
+/// Sound effects for gameplay events, loaded from a JSON file.
+///
+/// Every sound picks one of its files at random, jitters its pitch and
+/// is skipped while on cooldown or when too many copies of it, or too
+/// many sounds overall, are already playing. `events` maps gameplay
+/// event names to sounds.
+pub struct SoundBank {
+    sounds: HashMap<String, Sound>,
+    events: HashMap<String, String>,
+    max_voices: usize,
+    track: Option<SubTrackId>,
+    // when every playing sound ends
+    playing: Vec<Instant>,
+    rng: Rng,
+}
+
+struct Sound {
+    variations: Vec<SoundId>,
+    volume: f64,
+    pitch: f64,
+    pitch_jitter: f64,
+    cooldown: Duration,
+    voices: usize,
+    last_played: Option<Instant>,
+    playing: Vec<Instant>,
+}
+
+impl Default for SoundBank {
+    fn default() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Self {
+            sounds: HashMap::new(),
+            events: HashMap::new(),
+            max_voices: 8,
+            track: None,
+            playing: vec![],
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl SoundBank {
+    /// Loads sounds that play through the given track.
+    pub fn load(
+        audio: &mut AudioManager,
+        path: impl AsRef<Path>,
+        track: SubTrackId,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let json = read_json(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut bank = Self {
+            track: Some(track),
+            ..Default::default()
+        };
+        if let Some(max) = json["max_voices"].as_u64() {
+            bank.max_voices = max as usize;
+        }
+
+        let sound_vals = match &json["sounds"] {
+            Value::Object(map) => map,
+            _ => return Err(Error::Audio("sounds are not present".to_string())),
+        };
+        for (name, sound_val) in sound_vals {
+            let sound = match sound_val {
+                Value::Object(map) => map,
+                _ => return Err(Error::Audio("invalid sound".to_string())),
+            };
+            bank.sounds
+                .insert(name.to_string(), load_sound(audio, dir, sound)?);
+        }
+
+        bank.events = event_sounds(&json)?;
+        Ok(bank)
+    }
+
+    pub fn event_sound(&self, event: &str) -> Option<&str> {
+        self.events.get(event).map(|s| s.as_str())
+    }
+
+    /// Plays a sound unless it is held back by its cooldown or the voice
+    /// limits. Returns when it ends if it played.
+    pub fn play(&mut self, audio: &mut AudioManager, name: &str) -> Result<Option<Instant>> {
+        let now = Instant::now();
+        self.playing.retain(|end| *end > now);
+
+        let sound = self
+            .sounds
+            .get_mut(name)
+            .ok_or_else(|| Error::Audio("unknown sound".to_string()))?;
+        sound.playing.retain(|end| *end > now);
+
+        let cooling = matches!(sound.last_played, Some(t) if now - t < sound.cooldown);
+        if cooling || sound.playing.len() >= sound.voices || self.playing.len() >= self.max_voices {
+            return Ok(None);
+        }
+
+        let id = match self.rng.choose(&sound.variations) {
+            Some(id) => *id,
+            None => return Ok(None),
+        };
+        let jitter = (self.rng.float() as f64 * 2.0 - 1.0) * sound.pitch_jitter;
+        let pitch = (sound.pitch + jitter).max(0.01);
+
+        let mut settings = InstanceSettings::new().volume(sound.volume).pitch(pitch);
+        if let Some(track) = self.track {
+            settings = settings.track(track);
+        }
+        audio.play(id, settings)?;
+
+        // higher pitch plays faster
+        let end = now + Duration::from_secs_f64(id.duration() / pitch);
+        sound.last_played = Some(now);
+        sound.playing.push(end);
+        self.playing.push(end);
+        Ok(Some(end))
+    }
+}
+
+fn load_sound(audio: &mut AudioManager, dir: &Path, sound: &Map<String, Value>) -> Result<Sound> {
+    let files = match sound.get("files") {
+        Some(Value::Array(vec)) => vec,
+        _ => return Err(Error::Audio("sound has no files".to_string())),
+    };
+
+    let mut variations = vec![];
+    for file in files {
+        let file = file
+            .as_str()
+            .ok_or_else(|| Error::Audio("invalid sound file".to_string()))?;
+        variations.push(audio.load_sound(dir.join(file), Default::default())?);
+    }
+    if variations.is_empty() {
+        return Err(Error::Audio("sound has no files".to_string()));
+    }
+
+    let number =
+        |name: &str, default: f64| sound.get(name).and_then(|v| v.as_f64()).unwrap_or(default);
+
+    Ok(Sound {
+        variations,
+        volume: number("volume", 1.0),
+        pitch: number("pitch", 1.0),
+        pitch_jitter: number("pitch_jitter", 0.0),
+        cooldown: Duration::from_secs_f64(number("cooldown", 0.0).max(0.0)),
+        voices: number("voices", 1.0).max(1.0) as usize,
+        last_played: None,
+        playing: vec![],
+    })
+}
+
+fn read_json(path: &Path) -> Result<Value> {
+    let bytes = fs::read(path)?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Reads which sound each gameplay event plays, checking the sounds exist.
+fn event_sounds(json: &Value) -> Result<HashMap<String, String>> {
+    let mut events = HashMap::new();
+    if let Value::Object(map) = &json["events"] {
+        for (event, name) in map {
+            let name = name
+                .as_str()
+                .ok_or_else(|| Error::Audio("invalid event sound".to_string()))?;
+            if !json["sounds"][name].is_object() {
+                let message = format!("event {} uses unknown sound {}", event, name);
+                return Err(Error::Audio(message));
+            }
+            events.insert(event.to_string(), name.to_string());
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::level;
+    use crate::resources::Button;
+    use crate::tools;
+    use crate::world::World;
+    use crate::xsb;
+
+    #[test]
+    fn box_on_goal_plays_its_sound() {
+        let level = xsb::read_each("#####\n#@$.#\n#####\n").remove(0).1.unwrap();
+        let config = Config {
+            mute: true,
+            ..Default::default()
+        };
+        let mut world = World::new(&config).unwrap();
+        world.load_sounds("assets/sounds.json").unwrap();
+        level::spawn(&mut world, &level).unwrap();
+
+        world.set_buttons([Button::Right].iter().copied().collect());
+        tools::tick(&mut world, false);
+        world.set_buttons(HashSet::new());
+        for _ in 0..60 {
+            tools::tick(&mut world, false);
+        }
+
+        let goal = AudioEvent::Sound("goal".to_string());
+        assert!(world.audio_events().contains(&goal));
+    }
+
+    #[test]
+    fn unknown_sound_is_an_audio_error() {
+        let mut audio = NullAudio::default();
+        audio.load_sounds(Path::new("assets/sounds.json")).unwrap();
+        assert!(matches!(audio.play_sound("missing"), Err(Error::Audio(_))));
+    }
+}
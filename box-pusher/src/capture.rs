@@ -0,0 +1,111 @@
+use gif::Encoder;
+use gif::Frame;
+use gif::Repeat;
+use std::convert::TryFrom;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::crt::CrtSettings;
+use crate::error::Error;
+use crate::error::Result;
+use crate::render::Image;
+
+// gif delays count hundredths of a second
+const FRAME_DELAY: u16 = 4;
+const FRAME_TIME: f32 = FRAME_DELAY as f32 / 100.0;
+
+/// Collects frames for an animated GIF at a steady rate.
+///
+/// Frames are kept at the game's resolution and only scaled, or run
+/// through the CRT effect, when saved.
+pub struct Recorder {
+    frames: Vec<(Image, u16)>,
+    time: f32,
+    elapsed: f32,
+    duration: Option<f32>,
+}
+
+impl Recorder {
+    /// Records until stopped, or for `duration` seconds.
+    pub fn new(duration: Option<f32>) -> Self {
+        Self {
+            frames: vec![],
+            time: 0.0,
+            elapsed: 0.0,
+            duration,
+        }
+    }
+
+    pub fn add(&mut self, image: &Image, delta_time: f32) {
+        self.elapsed += delta_time;
+        if self.frames.is_empty() {
+            self.frames.push((image.clone(), FRAME_DELAY));
+            return;
+        }
+
+        // frames that did not change just show the last one for longer
+        self.time += delta_time;
+        while self.time >= FRAME_TIME {
+            self.time -= FRAME_TIME;
+            match self.frames.last_mut() {
+                Some((last, delay)) if last == image => *delay += FRAME_DELAY,
+                _ => self.frames.push((image.clone(), FRAME_DELAY)),
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.duration.map(|d| self.elapsed >= d).unwrap_or(false)
+    }
+
+    pub fn save_gif(
+        &self,
+        path: impl AsRef<Path>,
+        scale: u32,
+        crt: Option<&CrtSettings>,
+    ) -> Result<()> {
+        let (width, height) = match self.frames.first() {
+            Some((image, _)) => finish(image, scale, crt).size(),
+            None => return Err(Error::Image("no frames recorded".to_string())),
+        };
+        let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(Error::Image("recording is too large for a gif".to_string())),
+        };
+
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = Encoder::new(file, width, height, &[])?;
+        encoder.set_repeat(Repeat::Infinite)?;
+        for (image, delay) in &self.frames {
+            let mut data = finish(image, scale, crt).data;
+            let mut frame = Frame::from_rgba_speed(width, height, &mut data, 10);
+            frame.delay = *delay;
+            encoder.write_frame(&frame)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Scales a capture up, through the CRT effect if it is enabled.
+pub fn finish(image: &Image, scale: u32, crt: Option<&CrtSettings>) -> Image {
+    match crt {
+        Some(crt) if crt.enabled => crt.render_image(image, scale),
+        _ => image.scaled(scale),
+    }
+}
+
+/// Picks a file name in `dir` from the current time.
+pub fn capture_path(dir: &Path, suffix: &str) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    Ok(dir.join(format!("{}{}", millis, suffix)))
+}
@@ -1,21 +1,25 @@
-use duku::Handle;
-use duku::Texture;
+use duku::Rgb;
 use duku::Vec2;
 use specs::Component;
 use specs::DenseVecStorage;
+use specs::Entity;
 use specs_derive::Component;
 use std::collections::HashMap;
 
+use crate::particles::Effect;
+
 #[derive(Component)]
 pub struct Sprite {
-    pub texture: Handle<Texture>,
+    pub texture: String,
     pub part_pos: Vec2,
     pub part_size: Vec2,
 }
 
 #[derive(Component)]
 pub struct Animations {
+    /// Frames across and down the sheet.
     pub size: Vec2,
+    pub frame_size: Vec2,
     pub time: f32,
     pub animations: HashMap<String, Animation>,
     pub current_animation: String,
@@ -30,12 +34,20 @@ pub struct Animation {
 pub struct Position {
     pub x: i32,
     pub y: i32,
-    pub z: u32,
     pub offset: Vec2,
     pub direction: Direction,
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Render layer in the level's layer order, where 0 is drawn on top.
+/// Y sorted sprites stand on the layer and are drawn front to back
+/// over the flat ones.
+#[derive(Component)]
+pub struct Layer {
+    pub index: u32,
+    pub y_sort: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Direction {
     Up,
     Down,
@@ -43,6 +55,19 @@ pub enum Direction {
     Right,
 }
 
+impl Direction {
+    pub const ALL: [Direction; 4] = [Self::Up, Self::Down, Self::Left, Self::Right];
+
+    pub fn offset(self) -> (i32, i32) {
+        match self {
+            Self::Up => (0, 1),
+            Self::Down => (0, -1),
+            Self::Right => (1, 0),
+            Self::Left => (-1, 0),
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct Player;
 
@@ -51,3 +76,99 @@ pub struct Movable;
 
 #[derive(Component)]
 pub struct Immovable;
+
+#[derive(Component)]
+pub struct Goal;
+
+#[derive(Component)]
+pub struct BoxColor(pub Color);
+
+#[derive(Component)]
+pub struct GoalColor(pub Color);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Color {
+    Red,
+    Green,
+    Blue,
+    Yellow,
+}
+
+impl Color {
+    pub fn tint(self) -> Rgb {
+        match self {
+            Self::Red => Rgb::new(255, 110, 110),
+            Self::Green => Rgb::new(140, 255, 140),
+            Self::Blue => Rgb::new(120, 140, 255),
+            Self::Yellow => Rgb::new(255, 240, 110),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PressurePlate {
+    pub mode: PlateMode,
+    pub doors: Vec<Entity>,
+    pub pressed: bool,
+    pub active: bool,
+    pub up_part: Vec2,
+    pub down_part: Vec2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlateMode {
+    Toggle,
+    Hold,
+}
+
+#[derive(Component)]
+pub struct Door {
+    pub open: bool,
+    // as authored, linked plates flip it
+    pub default_open: bool,
+    pub closed_part: Vec2,
+    pub open_part: Vec2,
+}
+
+#[derive(Component)]
+pub struct Teleporter {
+    pub target: Option<Entity>,
+}
+
+#[derive(Component)]
+pub struct Warp {
+    pub time: f32,
+    pub duration: f32,
+}
+
+/// Feeds particles into the pool at a point in the level, in tiles,
+/// and goes away once its effect has finished spawning.
+#[derive(Component)]
+pub struct Emitter {
+    pub origin: Vec2,
+    pub effect: Effect,
+    pub time: f32,
+    pub spawned: u32,
+}
+
+impl Emitter {
+    pub fn new(effect: Effect, origin: Vec2) -> Self {
+        Self {
+            origin,
+            effect,
+            time: 0.0,
+            spawned: 0,
+        }
+    }
+
+    /// Emitter over the middle of a tile.
+    pub fn at_tile(effect: Effect, x: i32, y: i32) -> Self {
+        Self::new(effect, Vec2::new(x as f32 + 0.5, y as f32 - 0.5))
+    }
+}
+
+#[derive(Component)]
+pub struct OneWay(pub Direction);
+
+#[derive(Component)]
+pub struct Conveyor(pub Direction);
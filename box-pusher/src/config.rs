@@ -0,0 +1,376 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::crt::CrtSettings;
+use crate::error::Error;
+use crate::error::Result;
+use crate::generator::Difficulty;
+use crate::generator::Params;
+use crate::viewport::ScaleMode;
+
+pub const USAGE: &str = "\
+usage:
+    box-pusher [options]
+    box-pusher validate <file> [--budget <seconds>]
+    box-pusher solve <file> [--level <name>] [--budget <seconds>]
+    box-pusher generate [--width <n>] [--height <n>] [--boxes <n>]
+                        [--difficulty easy|medium|hard] [--seed <n>] [--out <file>]
+    box-pusher render <file or replay> [--level <name>] [--frames <n>] [--moves <lurd>]
+                      [--out <file>] [--gif <file>] [--scale <n>] [--crt <preset>]
+                      [--compare <file>]
+
+options:
+    --file <path>       level file, .ldtk or .xsb (default assets/world.ldtk)
+    --level <name>      level to start on (default first level)
+    --scale <n>         window scale (default 5)
+    --scale-mode <mode> integer, fit or stretch (default integer)
+    --fullscreen        start in fullscreen
+    --crt <preset>      CRT effect preset: off, subtle, classic or heavy
+    --no-crt            draw without the CRT shader
+    --mute              disable audio
+    --audio-log <file>  play without a sound device, writing sounds to a file
+    --replay <file>     play back a replay file
+    --save-replay <file> record this session's input to a replay file
+    --seed <n>          seed for generated levels
+    --hint-budget <seconds> time the solver gets for a hint (default 3)
+    --record <seconds>  record the start of the game to a GIF
+    --capture-dir <dir> where F12 screenshots and F11 recordings go (default captures)
+    --capture-scale <n> scale of recordings and CRT screenshots (default 3)
+    --help              show this message";
+
+// keeps the scaled view within the 65535 pixels a GIF can be across
+const MAX_SCALE: u32 = 64;
+
+// generated levels stay small enough to carve and solve
+const MIN_LEVEL_SIZE: i32 = 5;
+const MAX_LEVEL_SIZE: i32 = 64;
+const MAX_BOXES: usize = 16;
+
+/// Everything that can be set from the command line.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub command: Command,
+    pub level_file: PathBuf,
+    pub level_name: Option<String>,
+    pub tile_size: u32,
+    pub view_columns: u32,
+    pub view_rows: u32,
+    pub scale: u32,
+    pub scale_mode: ScaleMode,
+    pub fullscreen: bool,
+    pub crt: Option<CrtSettings>,
+    pub mute: bool,
+    pub audio_log: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub save_replay: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub record: Option<f32>,
+    pub capture_dir: PathBuf,
+    pub capture_scale: u32,
+    pub hint_budget: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Play,
+    Help,
+    Validate {
+        file: PathBuf,
+        budget: Duration,
+    },
+    Solve {
+        file: PathBuf,
+        level: Option<String>,
+        budget: Duration,
+    },
+    Generate {
+        params: Params,
+        out: Option<PathBuf>,
+    },
+    Render {
+        file: PathBuf,
+        level: Option<String>,
+        frames: u32,
+        moves: Option<String>,
+        out: PathBuf,
+        gif: Option<PathBuf>,
+        scale: u32,
+        crt: Option<CrtSettings>,
+        compare: Option<PathBuf>,
+    },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            command: Command::Play,
+            level_file: PathBuf::from("assets/world.ldtk"),
+            level_name: None,
+            tile_size: 16,
+            view_columns: 10,
+            view_rows: 9,
+            scale: 5,
+            scale_mode: ScaleMode::Integer,
+            fullscreen: false,
+            crt: None,
+            mute: false,
+            audio_log: None,
+            replay: None,
+            save_replay: None,
+            seed: None,
+            record: None,
+            capture_dir: PathBuf::from("captures"),
+            capture_scale: 3,
+            hint_budget: Duration::from_secs(3),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter().peekable();
+        let mut config = Self::default();
+        let budget = Duration::from_secs(10);
+
+        // subcommands take the first argument
+        config.command = match args.peek().map(|a| a.as_str()) {
+            Some("validate") => {
+                args.next();
+                Command::Validate {
+                    file: PathBuf::from(positional(&mut args, "file")?),
+                    budget,
+                }
+            }
+            Some("solve") => {
+                args.next();
+                Command::Solve {
+                    file: PathBuf::from(positional(&mut args, "file")?),
+                    level: None,
+                    budget,
+                }
+            }
+            Some("generate") => {
+                args.next();
+                Command::Generate {
+                    params: Params::default(),
+                    out: None,
+                }
+            }
+            Some("render") => {
+                args.next();
+                Command::Render {
+                    file: PathBuf::from(positional(&mut args, "file")?),
+                    level: None,
+                    frames: 0,
+                    moves: None,
+                    out: PathBuf::from("render.png"),
+                    gif: None,
+                    scale: 1,
+                    crt: None,
+                    compare: None,
+                }
+            }
+            _ => Command::Play,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| invalid(format!("{} needs a value", name)))
+            };
+
+            match (&mut config.command, arg.as_str()) {
+                (_, "--help") | (_, "-h") => config.command = Command::Help,
+
+                // tool options
+                (Command::Validate { budget, .. }, "--budget")
+                | (Command::Solve { budget, .. }, "--budget") => *budget = seconds(&value(&arg)?)?,
+                (Command::Solve { level, .. }, "--level")
+                | (Command::Render { level, .. }, "--level") => *level = Some(value(&arg)?),
+                (Command::Generate { params, .. }, "--width") => {
+                    params.width = within(&value(&arg)?, MIN_LEVEL_SIZE, MAX_LEVEL_SIZE)?
+                }
+                (Command::Generate { params, .. }, "--height") => {
+                    params.height = within(&value(&arg)?, MIN_LEVEL_SIZE, MAX_LEVEL_SIZE)?
+                }
+                (Command::Generate { params, .. }, "--boxes") => {
+                    params.boxes = within(&value(&arg)?, 1, MAX_BOXES)?
+                }
+                (Command::Generate { params, .. }, "--seed") => {
+                    params.seed = number(&value(&arg)?)?
+                }
+                (Command::Generate { params, .. }, "--difficulty") => {
+                    params.difficulty = match value(&arg)?.as_str() {
+                        "easy" => Difficulty::Easy,
+                        "medium" => Difficulty::Medium,
+                        "hard" => Difficulty::Hard,
+                        d => return Err(invalid(format!("unknown difficulty {}", d))),
+                    }
+                }
+                (Command::Generate { out, .. }, "--out") => {
+                    *out = Some(PathBuf::from(value(&arg)?))
+                }
+                (Command::Render { frames, .. }, "--frames") => *frames = number(&value(&arg)?)?,
+                (Command::Render { moves, .. }, "--moves") => *moves = Some(value(&arg)?),
+                (Command::Render { out, .. }, "--out") => *out = PathBuf::from(value(&arg)?),
+                (Command::Render { gif, .. }, "--gif") => *gif = Some(PathBuf::from(value(&arg)?)),
+                (Command::Render { scale, .. }, "--scale") => *scale = scale_factor(&value(&arg)?)?,
+                (Command::Render { crt, .. }, "--crt") => *crt = Some(crt_preset(&value(&arg)?)?),
+                (Command::Render { compare, .. }, "--compare") => {
+                    *compare = Some(PathBuf::from(value(&arg)?))
+                }
+
+                // game options
+                (Command::Play, "--file") => config.level_file = PathBuf::from(value(&arg)?),
+                (Command::Play, "--level") => config.level_name = Some(value(&arg)?),
+                (Command::Play, "--scale") => config.scale = scale_factor(&value(&arg)?)?,
+                (Command::Play, "--scale-mode") => {
+                    config.scale_mode = match value(&arg)?.as_str() {
+                        "integer" => ScaleMode::Integer,
+                        "fit" => ScaleMode::Fit,
+                        "stretch" => ScaleMode::Stretch,
+                        m => return Err(invalid(format!("unknown scale mode {}", m))),
+                    }
+                }
+                (Command::Play, "--fullscreen") => config.fullscreen = true,
+                (Command::Play, "--crt") => config.crt = Some(crt_preset(&value(&arg)?)?),
+                (Command::Play, "--no-crt") => config.crt = CrtSettings::preset("off"),
+                (Command::Play, "--mute") => config.mute = true,
+                (Command::Play, "--audio-log") => {
+                    config.audio_log = Some(PathBuf::from(value(&arg)?))
+                }
+                (Command::Play, "--replay") => config.replay = Some(PathBuf::from(value(&arg)?)),
+                (Command::Play, "--save-replay") => {
+                    config.save_replay = Some(PathBuf::from(value(&arg)?))
+                }
+                (Command::Play, "--seed") => config.seed = Some(number(&value(&arg)?)?),
+                (Command::Play, "--hint-budget") => config.hint_budget = seconds(&value(&arg)?)?,
+                (Command::Play, "--record") => {
+                    config.record = Some(seconds(&value(&arg)?)?.as_secs_f32())
+                }
+                (Command::Play, "--capture-dir") => {
+                    config.capture_dir = PathBuf::from(value(&arg)?)
+                }
+                (Command::Play, "--capture-scale") => {
+                    config.capture_scale = scale_factor(&value(&arg)?)?
+                }
+
+                _ => return Err(invalid(format!("unknown argument {}", arg))),
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn view_width(&self) -> u32 {
+        self.view_columns * self.tile_size
+    }
+
+    pub fn view_height(&self) -> u32 {
+        self.view_rows * self.tile_size
+    }
+}
+
+fn positional(args: &mut impl Iterator<Item = String>, name: &str) -> Result<String> {
+    match args.next() {
+        Some(arg) if !arg.starts_with("--") => Ok(arg),
+        _ => Err(invalid(format!("missing {}", name))),
+    }
+}
+
+fn crt_preset(name: &str) -> Result<CrtSettings> {
+    CrtSettings::preset(name).ok_or_else(|| invalid(format!("unknown crt preset {}", name)))
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("{} is not a number", value)))
+}
+
+/// Reads a time in seconds, which can't be negative or too large.
+fn seconds(value: &str) -> Result<Duration> {
+    let seconds: f32 = number(value)?;
+    if !seconds.is_finite() || seconds < 0.0 || seconds >= u64::MAX as f32 {
+        return Err(invalid(format!(
+            "{} is not a valid number of seconds",
+            value
+        )));
+    }
+    Ok(Duration::from_secs_f32(seconds))
+}
+
+/// Reads a scale, small enough for the scaled view to fit in a GIF.
+fn scale_factor(value: &str) -> Result<u32> {
+    let scale: u32 = number(value)?;
+    if scale > MAX_SCALE {
+        return Err(invalid(format!("scale {} is above {}", scale, MAX_SCALE)));
+    }
+    Ok(scale.max(1))
+}
+
+/// Reads a number that has to be from `low` to `high`.
+fn within<T>(value: &str, low: T, high: T) -> Result<T>
+where
+    T: std::str::FromStr + PartialOrd + std::fmt::Display,
+{
+    let number = number(value)?;
+    if number < low || number > high {
+        return Err(invalid(format!(
+            "{} is not from {} to {}",
+            value, low, high
+        )));
+    }
+    Ok(number)
+}
+
+fn invalid(message: String) -> Error {
+    Error::InvalidArgument(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config> {
+        Config::from_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn budget() {
+        assert_eq!(parse(&["--record", "4"]).unwrap().record, Some(4.0));
+        let config = parse(&["solve", "x", "--budget", "2.5"]).unwrap();
+        assert!(matches!(
+            config.command,
+            Command::Solve { budget, .. } if budget == Duration::from_millis(2500)
+        ));
+
+        for bad in &["-1", "NaN", "inf", "1e30", "soon"] {
+            assert!(parse(&["solve", "x", "--budget", bad]).is_err());
+            assert!(parse(&["--hint-budget", bad]).is_err());
+            assert!(parse(&["--record", bad]).is_err());
+        }
+    }
+
+    #[test]
+    fn generate_params() {
+        let config = parse(&["generate", "--width", "12", "--boxes", "4"]).unwrap();
+        assert!(matches!(
+            config.command,
+            Command::Generate { params, .. } if params.width == 12 && params.boxes == 4
+        ));
+
+        assert!(parse(&["generate", "--width", "100000", "--height", "100000"]).is_err());
+        assert!(parse(&["generate", "--height", "2"]).is_err());
+        assert!(parse(&["generate", "--boxes", "0"]).is_err());
+        assert!(parse(&["generate", "--boxes", "1000"]).is_err());
+    }
+
+    #[test]
+    fn scale() {
+        assert_eq!(parse(&["--scale", "0"]).unwrap().scale, 1);
+        assert_eq!(parse(&["--capture-scale", "64"]).unwrap().capture_scale, 64);
+        assert!(parse(&["--capture-scale", "65"]).is_err());
+        assert!(parse(&["render", "x", "--scale", "100000"]).is_err());
+    }
+}
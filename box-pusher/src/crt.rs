@@ -0,0 +1,215 @@
+use duku::Material;
+use serde_json::json;
+use serde_json::Value;
+use std::f32::consts::PI;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::render::Image;
+
+pub const PRESETS: [&str; 4] = ["off", "subtle", "classic", "heavy"];
+
+// largest value of each setting, the options menu goes as far
+pub const MAX_CURVATURE: f32 = 0.5;
+pub const MAX_SCANLINES: f32 = 1.0;
+pub const MAX_VIGNETTE: f32 = 1.0;
+pub const MAX_BLOOM: f32 = 1.0;
+pub const MAX_ABERRATION: f32 = 4.0;
+
+/// Settings for the CRT shader, written into its material.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CrtSettings {
+    pub enabled: bool,
+    pub curvature: f32,
+    pub scanlines: f32,
+    pub vignette: f32,
+    pub bloom: f32,
+    pub aberration: f32,
+}
+
+impl Default for CrtSettings {
+    fn default() -> Self {
+        Self::preset("classic").expect("bad preset")
+    }
+}
+
+impl CrtSettings {
+    pub fn preset(name: &str) -> Option<Self> {
+        let (curvature, scanlines, vignette, bloom, aberration) = match name {
+            "off" => {
+                return Some(Self {
+                    enabled: false,
+                    curvature: 0.0,
+                    scanlines: 0.0,
+                    vignette: 0.0,
+                    bloom: 0.0,
+                    aberration: 0.0,
+                })
+            }
+            "subtle" => (0.08, 0.25, 0.5, 0.1, 0.0),
+            "classic" => (0.17, 0.5, 1.0, 0.0, 0.0),
+            "heavy" => (0.25, 0.7, 1.0, 0.4, 1.0),
+            _ => return None,
+        };
+
+        Some(Self {
+            enabled: true,
+            curvature,
+            scanlines,
+            vignette,
+            bloom,
+            aberration,
+        })
+    }
+
+    /// Reads settings from JSON, starting from its `preset` and
+    /// overriding any values it sets.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let mut settings = match &json["preset"] {
+            Value::String(name) => Self::preset(name)
+                .ok_or_else(|| Error::InvalidJson(format!("unknown crt preset {}", name)))?,
+            _ => Self::default(),
+        };
+
+        // hand edited values are kept within what the menu allows
+        let number = |name: &str, default: f32, max: f32| match &json[name] {
+            Value::Number(n) => n
+                .as_f64()
+                .map(|n| (n as f32).clamp(0.0, max))
+                .unwrap_or(default),
+            _ => default,
+        };
+        settings.curvature = number("curvature", settings.curvature, MAX_CURVATURE);
+        settings.scanlines = number("scanlines", settings.scanlines, MAX_SCANLINES);
+        settings.vignette = number("vignette", settings.vignette, MAX_VIGNETTE);
+        settings.bloom = number("bloom", settings.bloom, MAX_BLOOM);
+        settings.aberration = number("aberration", settings.aberration, MAX_ABERRATION);
+        if let Value::Bool(enabled) = &json["enabled"] {
+            settings.enabled = *enabled;
+        }
+
+        Ok(settings)
+    }
+
+    pub fn to_json(self) -> Value {
+        json!({
+            "enabled": self.enabled,
+            "curvature": self.curvature,
+            "scanlines": self.scanlines,
+            "vignette": self.vignette,
+            "bloom": self.bloom,
+            "aberration": self.aberration,
+        })
+    }
+
+    pub fn apply(&self, material: &mut Material) {
+        material.b.x = self.curvature;
+        material.b.y = self.curvature;
+        material.c.x = self.scanlines;
+        material.c.y = self.vignette;
+        material.c.z = self.bloom;
+        material.c.w = self.aberration;
+    }
+
+    /// Runs the CRT effect on the CPU, the same way `crt.glsl` does, for
+    /// captures taken outside of the GPU pipeline.
+    pub fn render_image(&self, image: &Image, scale: u32) -> Image {
+        let mut out = Image::new(image.width * scale.max(1), image.height * scale.max(1));
+        let size = (image.width as f32, image.height as f32);
+
+        // black outside of the image, like the border wrap
+        let sample = |u: f32, v: f32| -> [f32; 3] {
+            if u < 0.0 || v < 0.0 || u >= 1.0 || v >= 1.0 {
+                return [0.0; 3];
+            }
+            let p = image.pixel((u * size.0) as u32, (v * size.1) as u32);
+            [
+                p[0] as f32 / 255.0,
+                p[1] as f32 / 255.0,
+                p[2] as f32 / 255.0,
+            ]
+        };
+
+        for y in 0..out.height {
+            for x in 0..out.width {
+                let in_u = (x as f32 + 0.5) / out.width as f32;
+                let in_v = (y as f32 + 0.5) / out.height as f32;
+
+                // remap the uvs
+                let curve = |a: f32, b: f32| {
+                    let (a, b) = (a * 2.0 - 1.0, b * 2.0 - 1.0);
+                    let offset = b.abs() * self.curvature;
+                    (a + a * offset * offset) * 0.5 + 0.5
+                };
+                let u = curve(in_u, in_v);
+                let v = curve(in_v, in_u);
+                if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+                    out.set_pixel(x, y, [0, 0, 0, 255]);
+                    continue;
+                }
+
+                // split the color channels apart
+                let shift = self.aberration / size.0;
+                let mut color = [
+                    sample(u + shift, v)[0],
+                    sample(u, v)[1],
+                    sample(u - shift, v)[2],
+                ];
+
+                // add a glow from the surrounding pixels
+                let mut glow = [0.0; 3];
+                for gx in -2..=2 {
+                    for gy in -2..=2 {
+                        let s = sample(u + gx as f32 * 1.5 / size.0, v + gy as f32 * 1.5 / size.1);
+                        (0..3).for_each(|i| glow[i] += s[i]);
+                    }
+                }
+                (0..3).for_each(|i| color[i] += glow[i] / 25.0 * self.bloom);
+
+                // calculate scanlines
+                let wave = |t: f32, n: f32| ((t + 0.005) * n * PI * 2.0).sin() * 0.5 + 0.5;
+                let scan = smoothstep(0.05, 0.3, wave(u, size.0) * wave(v, size.1))
+                    * self.scanlines
+                    + (1.0 - self.scanlines);
+
+                // calculate vignette
+                let fade = |t: f32| (1.0 - (t * 2.0 - 1.0).powi(4)).clamp(0.0, 1.0);
+                let vignette = 1.0 + (fade(in_u) * fade(in_v) - 1.0) * self.vignette;
+
+                let channel = |c: f32| ((c * scan * vignette).clamp(0.0, 1.0) * 255.0) as u8;
+                out.set_pixel(
+                    x,
+                    y,
+                    [channel(color[0]), channel(color[1]), channel(color[2]), 255],
+                );
+            }
+        }
+
+        out
+    }
+}
+
+fn smoothstep(from: f32, to: f32, t: f32) -> f32 {
+    let t = ((t - from) / (to - from)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_json_clamps() {
+        let json = json!({ "scanlines": -1.0, "curvature": 10.0, "aberration": 2.0 });
+        let settings = CrtSettings::from_json(&json).unwrap();
+        assert_eq!(settings.scanlines, 0.0);
+        assert_eq!(settings.curvature, MAX_CURVATURE);
+        assert_eq!(settings.aberration, 2.0);
+
+        let json = json!({ "preset": "wobbly" });
+        assert!(matches!(
+            CrtSettings::from_json(&json),
+            Err(Error::InvalidJson(_))
+        ));
+    }
+}
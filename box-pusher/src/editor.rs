@@ -0,0 +1,324 @@
+use duku::window::Events;
+use duku::window::Key;
+use duku::window::MouseButton;
+use duku::Vec2;
+use std::collections::HashSet;
+use std::fs;
+
+use crate::components::Color;
+use crate::error::Result;
+use crate::level;
+use crate::level::Instance;
+use crate::level::InstanceKind;
+use crate::level::Level;
+use crate::level::Tile;
+use crate::level::TileLayer;
+use crate::systems::Overlay;
+use crate::validate;
+use crate::viewport::Viewport;
+use crate::world::World;
+use crate::xsb;
+
+const LDTK_PATH: &str = "assets/edited.ldtk";
+const XSB_PATH: &str = "assets/edited.xsb";
+
+/// Edit mode for building levels inside the game.
+///
+/// Tab switches between editing and playing, number keys pick a brush,
+/// the mouse or arrow keys move the cursor, left click or space places
+/// and right click or backspace erases. C cycles the color of boxes and
+/// goals, Z undoes the last edit, F2 saves to LDtk and F3 to XSB.
+pub struct Editor {
+    pub active: bool,
+    level: Level,
+    edits: Vec<Level>,
+    cursor: (i32, i32),
+    mouse: Vec2,
+    brush: Brush,
+    color: Option<Color>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Brush {
+    Wall,
+    Floor,
+    Box,
+    Goal,
+    Player,
+}
+
+impl Editor {
+    pub fn new(level: Level) -> Self {
+        Self {
+            active: false,
+            level,
+            edits: vec![],
+            cursor: (0, 1),
+            mouse: Vec2::default(),
+            brush: Brush::Wall,
+            color: None,
+        }
+    }
+
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+
+    pub fn set_level(&mut self, level: Level) {
+        self.level = level;
+        self.edits.clear();
+    }
+
+    pub fn overlay(&self) -> Option<Overlay> {
+        if self.active {
+            Some(Overlay {
+                width: self.level.width,
+                height: self.level.height,
+                cursor: self.cursor,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        events: &Events,
+        world: &mut World,
+        viewport: &Viewport,
+    ) -> Result<()> {
+        // switch between editing and test playing
+        if events.is_key_typed(Key::Tab) {
+            self.active = !self.active;
+            if !self.active {
+                report(&self.level);
+            }
+            return respawn(world, &self.level);
+        }
+
+        if !self.active {
+            return Ok(());
+        }
+
+        // pick brush
+        let brushes = [
+            (Key::Key1, Brush::Wall),
+            (Key::Key2, Brush::Floor),
+            (Key::Key3, Brush::Box),
+            (Key::Key4, Brush::Goal),
+            (Key::Key5, Brush::Player),
+        ];
+        for (key, brush) in &brushes {
+            if events.is_key_typed(*key) {
+                self.brush = *brush;
+            }
+        }
+        if events.is_key_typed(Key::C) {
+            self.color = match self.color {
+                None => Some(Color::Red),
+                Some(Color::Red) => Some(Color::Green),
+                Some(Color::Green) => Some(Color::Blue),
+                Some(Color::Blue) => Some(Color::Yellow),
+                Some(Color::Yellow) => None,
+            };
+        }
+
+        // move cursor
+        let (x, y) = self.cursor;
+        if events.is_key_typed(Key::Up) {
+            self.cursor = (x, y + 1);
+        }
+        if events.is_key_typed(Key::Down) {
+            self.cursor = (x, y - 1);
+        }
+        if events.is_key_typed(Key::Left) {
+            self.cursor = (x - 1, y);
+        }
+        if events.is_key_typed(Key::Right) {
+            self.cursor = (x + 1, y);
+        }
+        let mouse = events.mouse_position();
+        if mouse != self.mouse {
+            self.mouse = mouse;
+            if let Some(cursor) = viewport.screen_to_grid(mouse) {
+                self.cursor = cursor;
+            }
+        }
+        self.cursor.0 = self.cursor.0.max(0).min(self.level.width - 1);
+        self.cursor.1 = self.cursor.1.max(1).min(self.level.height);
+
+        // edit level
+        let place = events.is_button_pressed(MouseButton::Left) || events.is_key_typed(Key::Space);
+        let erase = events.is_button_pressed(MouseButton::Right)
+            || events.is_key_typed(Key::Back)
+            || events.is_key_typed(Key::Delete);
+        let mut changed = false;
+        if place {
+            changed |= self.edit(paint);
+        } else if erase {
+            changed |= self.edit(|level, tile, _, _| clear(level, tile));
+        }
+        if events.is_key_typed(Key::Z) {
+            if let Some(level) = self.edits.pop() {
+                self.level = level;
+                changed = true;
+            }
+        }
+        if changed {
+            respawn(world, &self.level)?;
+        }
+
+        // save level
+        if events.is_key_typed(Key::F2) {
+            report(&self.level);
+            level::save(LDTK_PATH, std::slice::from_ref(&self.level))?;
+            println!("saved {}", LDTK_PATH);
+        }
+        if events.is_key_typed(Key::F3) {
+            report(&self.level);
+            fs::write(XSB_PATH, xsb::write(&self.level)?)?;
+            println!("saved {}", XSB_PATH);
+        }
+
+        Ok(())
+    }
+
+    /// Applies an edit at the cursor and remembers the level before it
+    /// for undo. Returns if anything changed.
+    fn edit(&mut self, f: impl Fn(&mut Level, (i32, i32), Brush, Option<Color>)) -> bool {
+        let before = self.level.clone();
+        f(&mut self.level, self.cursor, self.brush, self.color);
+        drop_references(&mut self.level);
+        if self.level == before {
+            return false;
+        }
+        self.edits.push(before);
+        true
+    }
+}
+
+fn respawn(world: &mut World, level: &Level) -> Result<()> {
+    world.clear();
+    level::spawn(world, level)
+}
+
+fn report(level: &Level) {
+    for problem in validate::validate(level) {
+        println!("{}: {}", level.name, problem);
+    }
+}
+
+fn paint(level: &mut Level, (x, y): (i32, i32), brush: Brush, color: Option<Color>) {
+    // everything but walls stands on floor
+    if brush != Brush::Wall {
+        level
+            .tiles
+            .retain(|t| !(t.layer == TileLayer::Collisions && (t.x, t.y) == (x, y)));
+    }
+    add_tile(level, TileLayer::Background, x, y);
+
+    let kind = match brush {
+        Brush::Wall => {
+            level.instances.retain(|i| (i.x, i.y) != (x, y));
+            add_tile(level, TileLayer::Collisions, x, y);
+            return;
+        }
+        Brush::Floor => return,
+        Brush::Box => {
+            level
+                .instances
+                .retain(|i| (i.x, i.y) != (x, y) || matches!(i.kind, InstanceKind::Goal(_)));
+            InstanceKind::Box(color)
+        }
+        Brush::Goal => {
+            level.instances.retain(|i| {
+                (i.x, i.y) != (x, y)
+                    || matches!(i.kind, InstanceKind::Box(_) | InstanceKind::Player)
+            });
+            InstanceKind::Goal(color)
+        }
+        Brush::Player => {
+            // there can only be one player
+            level.instances.retain(|i| {
+                !matches!(i.kind, InstanceKind::Player)
+                    && ((i.x, i.y) != (x, y) || matches!(i.kind, InstanceKind::Goal(_)))
+            });
+            InstanceKind::Player
+        }
+    };
+
+    level.instances.push(Instance {
+        iid: None,
+        kind,
+        x,
+        y,
+    });
+}
+
+fn clear(level: &mut Level, (x, y): (i32, i32)) {
+    let here = |tx: i32, ty: i32| (tx, ty) == (x, y);
+
+    // erase things first, then walls, then the floor
+    if level.instances.iter().any(|i| here(i.x, i.y)) {
+        level.instances.retain(|i| !here(i.x, i.y));
+    } else if level
+        .tiles
+        .iter()
+        .any(|t| t.layer == TileLayer::Collisions && here(t.x, t.y))
+    {
+        level
+            .tiles
+            .retain(|t| !(t.layer == TileLayer::Collisions && here(t.x, t.y)));
+    } else {
+        level.tiles.retain(|t| !here(t.x, t.y));
+    }
+}
+
+/// Unlinks plates and teleporters from instances that are gone, so the
+/// level can still be spawned.
+fn drop_references(level: &mut Level) {
+    let iids: HashSet<_> = level
+        .instances
+        .iter()
+        .filter_map(|i| i.iid.clone())
+        .collect();
+
+    for instance in &mut level.instances {
+        match &mut instance.kind {
+            InstanceKind::PressurePlate { doors, .. } => doors.retain(|d| iids.contains(d)),
+            InstanceKind::Teleporter { target } => {
+                *target = target.take().filter(|t| iids.contains(t))
+            }
+            _ => (),
+        }
+    }
+}
+
+fn add_tile(level: &mut Level, layer: TileLayer, x: i32, y: i32) {
+    if level
+        .tiles
+        .iter()
+        .any(|t| t.layer == layer && (t.x, t.y) == (x, y))
+    {
+        return;
+    }
+
+    // reuse the look of tiles already in the level
+    let (tileset, part_pos) = level
+        .tiles
+        .iter()
+        .find(|t| t.layer == layer)
+        .map(|t| (t.tileset.clone(), t.part_pos))
+        .unwrap_or_else(|| match layer {
+            TileLayer::Collisions => ("wall.png".to_string(), Vec2::new(32.0, 48.0)),
+            TileLayer::Background => ("floor.png".to_string(), Vec2::new(0.0, 16.0)),
+        });
+
+    level.tiles.push(Tile {
+        layer,
+        x,
+        y,
+        tileset,
+        part_pos,
+    });
+}
@@ -11,6 +11,10 @@ pub enum Error {
     Io(String),
     Duku(String),
     InvalidLevel(String),
+    InvalidArgument(String),
+    Audio(String),
+    Image(String),
+    InvalidReplay(String),
 }
 
 impl error::Error for Error {}
@@ -39,6 +43,30 @@ impl From<duku::Error> for Error {
     }
 }
 
+impl From<kira::AudioError> for Error {
+    fn from(e: kira::AudioError) -> Self {
+        Self::Audio(format!("{}", e))
+    }
+}
+
+impl From<png::DecodingError> for Error {
+    fn from(e: png::DecodingError) -> Self {
+        Self::Image(format!("{}", e))
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(e: png::EncodingError) -> Self {
+        Self::Image(format!("{}", e))
+    }
+}
+
+impl From<gif::EncodingError> for Error {
+    fn from(e: gif::EncodingError) -> Self {
+        Self::Image(format!("{}", e))
+    }
+}
+
 impl From<&str> for Error {
     fn from(e: &str) -> Self {
         Self::InvalidLevel(e.to_string())
@@ -0,0 +1,408 @@
+use duku::Vec2;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use crate::components::Direction;
+use crate::error::Result;
+use crate::level;
+use crate::level::Instance;
+use crate::level::InstanceKind;
+use crate::level::Level;
+use crate::level::Tile as LevelTile;
+use crate::level::TileLayer;
+use crate::random::Rng;
+use crate::resources::Rules;
+use crate::solver;
+use crate::solver::Move;
+use crate::solver::Outcome;
+use crate::solver::Puzzle;
+use crate::solver::Tile;
+
+const ATTEMPTS: usize = 50;
+const SOLVE_BUDGET: Duration = Duration::from_secs(2);
+
+// room pieces that get stamped together into a level
+const TEMPLATES: [[&str; 3]; 8] = [
+    ["   ", "   ", "   "],
+    ["#  ", "   ", "   "],
+    ["## ", "   ", "   "],
+    ["###", "   ", "   "],
+    ["#  ", "#  ", "   "],
+    [" # ", "   ", "   "],
+    ["   ", " # ", "   "],
+    ["#  ", "   ", "  #"],
+];
+
+#[derive(Debug, Copy, Clone)]
+pub struct Params {
+    pub width: i32,
+    pub height: i32,
+    pub boxes: usize,
+    pub difficulty: Difficulty,
+    pub seed: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            width: 10,
+            height: 9,
+            boxes: 3,
+            difficulty: Difficulty::Medium,
+            seed: 0,
+        }
+    }
+}
+
+impl Difficulty {
+    fn range(self) -> (u32, u32) {
+        match self {
+            Self::Easy => (0, 15),
+            Self::Medium => (15, 35),
+            Self::Hard => (35, u32::MAX),
+        }
+    }
+}
+
+/// Generates a solvable level by carving a room, placing goals and then
+/// pulling boxes away from the goals backwards.
+pub fn generate(params: &Params) -> Result<Level> {
+    let mut rng = Rng::new(params.seed);
+    let (low, high) = params.difficulty.range();
+
+    let mut best: Option<(u32, Puzzle)> = None;
+    for _ in 0..ATTEMPTS {
+        let floor = match carve(&mut rng, params) {
+            Some(floor) => floor,
+            None => continue,
+        };
+        let puzzle = match scramble(&mut rng, params, floor) {
+            Some(puzzle) => puzzle,
+            None => continue,
+        };
+
+        // make sure it can be solved
+        let solution = match solver::solve(&puzzle, SOLVE_BUDGET) {
+            Outcome::Solved(solution) => solution,
+            _ => continue,
+        };
+        let score = score(&puzzle, &solution.moves);
+
+        if score >= low && score < high {
+            return Ok(to_level(params, &puzzle));
+        }
+
+        // remember the closest one in case nothing fits
+        let distance = |s: u32| {
+            if s < low {
+                low - s
+            } else {
+                s.saturating_sub(high)
+            }
+        };
+        if best
+            .as_ref()
+            .map(|b| distance(score) < distance(b.0))
+            .unwrap_or(true)
+        {
+            best = Some((score, puzzle));
+        }
+    }
+
+    match best {
+        Some((_, puzzle)) => Ok(to_level(params, &puzzle)),
+        None => Err("could not generate level".into()),
+    }
+}
+
+/// Generates a level on another thread, so the game keeps running.
+pub fn request(params: Params) -> Receiver<Result<Level>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // the game may have quit without waiting
+        let _ = sender.send(generate(&params));
+    });
+    receiver
+}
+
+/// Rates how hard a solution is, mostly by pushes and by how often
+/// the player has to switch between boxes.
+pub fn score(puzzle: &Puzzle, moves: &[Move]) -> u32 {
+    let mut boxes: Vec<Tile> = puzzle.boxes.iter().map(|b| b.0).collect();
+    let mut player = puzzle.player;
+    let mut last_box = None;
+    let mut pushes = 0;
+    let mut switches = 0;
+
+    for m in moves {
+        let (xo, yo) = m.direction.offset();
+        player = (player.0 + xo, player.1 + yo);
+
+        if m.push {
+            if let Some(i) = boxes.iter().position(|b| *b == player) {
+                boxes[i] = (player.0 + xo, player.1 + yo);
+                if last_box.is_some() && last_box != Some(i) {
+                    switches += 1;
+                }
+                last_box = Some(i);
+            }
+            pushes += 1;
+        }
+    }
+
+    pushes + switches * 2
+}
+
+fn carve(rng: &mut Rng, params: &Params) -> Option<HashSet<Tile>> {
+    let (w, h) = (params.width, params.height);
+    let inside = |(x, y): Tile| x > 0 && x < w - 1 && y > 1 && y < h;
+
+    // stamp random templates over the whole level
+    let mut floor = HashSet::new();
+    for bx in (1..w - 1).step_by(3) {
+        for by in (2..h).step_by(3) {
+            let template = rng.choose(&TEMPLATES)?;
+            let rotation = rng.range(4);
+            for (ty, row) in template.iter().enumerate() {
+                for (tx, c) in row.chars().enumerate() {
+                    let (tx, ty) = rotate(tx as i32, ty as i32, rotation);
+                    let tile = (bx + tx, by + ty);
+                    if c == ' ' && inside(tile) {
+                        floor.insert(tile);
+                    }
+                }
+            }
+        }
+    }
+
+    // keep only the largest connected room
+    let mut largest = HashSet::new();
+    let mut left = floor.clone();
+    while let Some(start) = left.iter().next().copied() {
+        let room = connected(&floor, start);
+        for tile in &room {
+            left.remove(tile);
+        }
+        if room.len() > largest.len() {
+            largest = room;
+        }
+    }
+
+    if largest.len() < params.boxes * 3 + 4 {
+        None
+    } else {
+        Some(largest)
+    }
+}
+
+fn scramble(rng: &mut Rng, params: &Params, floor: HashSet<Tile>) -> Option<Puzzle> {
+    let mut tiles: Vec<_> = floor.iter().copied().collect();
+    tiles.sort();
+    rng.shuffle(&mut tiles);
+
+    // boxes start out solved on their goals
+    let goals: Vec<_> = tiles.iter().take(params.boxes).copied().collect();
+    let mut boxes = goals.clone();
+    let mut player = *tiles.get(params.boxes)?;
+
+    // play backwards by walking around and pulling boxes
+    let steps = 40 * params.boxes;
+    for _ in 0..steps {
+        let direction = *rng.choose(&Direction::ALL)?;
+        let (xo, yo) = direction.offset();
+        let next = (player.0 + xo, player.1 + yo);
+        if !floor.contains(&next) || boxes.contains(&next) {
+            continue;
+        }
+
+        let behind = (player.0 - xo, player.1 - yo);
+        if let Some(i) = boxes.iter().position(|b| *b == behind) {
+            if rng.chance(0.7) {
+                boxes[i] = player;
+            }
+        }
+        player = next;
+    }
+
+    if boxes.iter().all(|b| goals.contains(b)) {
+        return None;
+    }
+
+    Some(Puzzle {
+        floor,
+        goals: goals.into_iter().map(|g| (g, None)).collect(),
+        boxes: boxes.into_iter().map(|b| (b, None)).collect(),
+        player,
+    })
+}
+
+fn to_level(params: &Params, puzzle: &Puzzle) -> Level {
+    let mut tiles = vec![];
+    for x in 0..params.width {
+        for y in 1..=params.height {
+            tiles.push(LevelTile {
+                layer: TileLayer::Background,
+                x,
+                y,
+                tileset: "floor.png".to_string(),
+                part_pos: Vec2::new(0.0, 16.0),
+            });
+            if !puzzle.floor.contains(&(x, y)) {
+                tiles.push(LevelTile {
+                    layer: TileLayer::Collisions,
+                    x,
+                    y,
+                    tileset: "wall.png".to_string(),
+                    part_pos: Vec2::new(32.0, 48.0),
+                });
+            }
+        }
+    }
+
+    let mut goals: Vec<_> = puzzle.goals.keys().copied().collect();
+    goals.sort();
+    let instances = goals
+        .into_iter()
+        .map(|(x, y)| (InstanceKind::Goal(None), x, y))
+        .chain(
+            puzzle
+                .boxes
+                .iter()
+                .map(|((x, y), color)| (InstanceKind::Box(*color), *x, *y)),
+        )
+        .chain(Some((
+            InstanceKind::Player,
+            puzzle.player.0,
+            puzzle.player.1,
+        )))
+        .map(|(kind, x, y)| Instance {
+            iid: None,
+            kind,
+            x,
+            y,
+        })
+        .collect();
+
+    Level {
+        name: format!("Generated{}", params.seed),
+        width: params.width,
+        height: params.height,
+        grid_size: 16,
+        rules: Rules::default(),
+        music: None,
+        tiles,
+        instances,
+        layers: level::default_layers(),
+        unknown: vec![],
+    }
+}
+
+fn connected(floor: &HashSet<Tile>, start: Tile) -> HashSet<Tile> {
+    let mut room = HashSet::new();
+    let mut queue = VecDeque::new();
+    room.insert(start);
+    queue.push_back(start);
+
+    while let Some((x, y)) = queue.pop_front() {
+        for direction in &Direction::ALL {
+            let (xo, yo) = direction.offset();
+            let next = (x + xo, y + yo);
+            if floor.contains(&next) && room.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    room
+}
+
+fn rotate(x: i32, y: i32, times: usize) -> (i32, i32) {
+    (0..times).fold((x, y), |(x, y), _| (2 - y, x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate;
+    use crate::validate::Solvability;
+    use crate::xsb;
+
+    fn params(seed: u64) -> Params {
+        Params {
+            width: 8,
+            height: 8,
+            boxes: 2,
+            difficulty: Difficulty::Easy,
+            seed,
+        }
+    }
+
+    fn solved_score(text: &str) -> u32 {
+        let level = xsb::read_each(text).remove(0).1.unwrap();
+        let puzzle = Puzzle::from_level(&level).unwrap();
+        match solver::solve(&puzzle, Duration::from_secs(1)) {
+            Outcome::Solved(solution) => score(&puzzle, &solution.moves),
+            _ => panic!("not solved"),
+        }
+    }
+
+    #[test]
+    fn same_seed_same_level() {
+        let a = generate(&params(7)).unwrap();
+        let b = generate(&params(7)).unwrap();
+        assert!(a == b);
+    }
+
+    #[test]
+    fn generated_levels_are_valid_and_solvable() {
+        for seed in 0..4 {
+            let level = generate(&params(seed)).unwrap();
+            assert!(validate::validate(&level).is_empty());
+            let check = validate::check_solvable(&level, SOLVE_BUDGET);
+            assert!(matches!(check, Solvability::Solved));
+        }
+    }
+
+    #[test]
+    fn score_orders_by_difficulty() {
+        let easy = solved_score("#####\n#@$.#\n#####\n");
+        let hard = solved_score(
+            "\
+#######
+#.  . #
+# $$  #
+#  @  #
+#  $ .#
+#######
+",
+        );
+        assert!(easy < hard);
+
+        // levels generated for each difficulty score in the same order
+        let generated = |difficulty| {
+            let level = generate(&Params {
+                difficulty,
+                ..Params::default()
+            })
+            .unwrap();
+            let puzzle = Puzzle::from_level(&level).unwrap();
+            match solver::solve(&puzzle, SOLVE_BUDGET) {
+                Outcome::Solved(solution) => score(&puzzle, &solution.moves),
+                _ => panic!("not solved"),
+            }
+        };
+        let (easy, hard) = (generated(Difficulty::Easy), generated(Difficulty::Hard));
+        assert!(easy < hard);
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::components::Direction;
+use crate::solver;
+use crate::solver::Board;
+use crate::solver::Outcome;
+use crate::solver::Puzzle;
+use crate::solver::Tile;
+use crate::world::World;
+
+/// What the solver suggests from a board.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hint {
+    /// Walk along `path`, then push the box on `tile` in `direction`.
+    Push {
+        path: Vec<Tile>,
+        tile: Tile,
+        direction: Direction,
+    },
+    Solved,
+    /// No solution from here, but there is one this many moves back.
+    Undo(usize),
+    /// No solution from here or anywhere in the undo history.
+    Stuck,
+    /// Ran out of time before finding anything.
+    GaveUp,
+}
+
+impl Hint {
+    /// Marks pushes on the board, everything else is told in words.
+    pub fn show(self, world: &mut World) {
+        match self {
+            Self::Push {
+                path,
+                tile,
+                direction,
+            } => world.show_hint(path, Some((tile, direction))),
+            Self::Solved => world.show_message("hint: the level is already solved"),
+            Self::Undo(1) => world.show_message("hint: no solution from here, undo 1 move"),
+            Self::Undo(n) => {
+                world.show_message(format!("hint: no solution from here, undo {} moves", n))
+            }
+            Self::Stuck => world.show_message("hint: no solution from here"),
+            Self::GaveUp => world.show_message("hint: no solution found in time"),
+        }
+    }
+}
+
+/// Looks for a hint on another thread, so the game keeps running.
+///
+/// `boards` is the current board followed by the undo history, newest
+/// first. They are only searched if there is no solution from the
+/// current board, all within the one budget.
+pub fn request(puzzle: Puzzle, boards: Vec<Board>, budget: Duration) -> Receiver<Hint> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // the game may have quit or moved on without waiting
+        let _ = sender.send(find(&puzzle, &boards, budget));
+    });
+    receiver
+}
+
+fn find(puzzle: &Puzzle, boards: &[Board], budget: Duration) -> Hint {
+    let deadline = Instant::now() + budget;
+
+    for (undos, board) in boards.iter().enumerate() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let solution = match solver::solve(&puzzle.with_board(board), remaining) {
+            Outcome::Solved(solution) => solution,
+            Outcome::Unsolvable => continue,
+            Outcome::GaveUp => return Hint::GaveUp,
+        };
+        if undos > 0 {
+            return Hint::Undo(undos);
+        }
+
+        // walk up to the first push
+        let mut path = vec![];
+        let mut player = board.player;
+        for m in solution.moves {
+            let (xo, yo) = m.direction.offset();
+            let next = (player.0 + xo, player.1 + yo);
+            if m.push {
+                return Hint::Push {
+                    path,
+                    tile: next,
+                    direction: m.direction,
+                };
+            }
+            path.push(next);
+            player = next;
+        }
+        return Hint::Solved;
+    }
+
+    Hint::Stuck
+}
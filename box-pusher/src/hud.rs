@@ -0,0 +1,27 @@
+use duku::ShapeMode;
+use duku::Target;
+use duku::Vec2;
+
+use crate::resources::Message;
+
+/// Draws messages for the player in the bottom left of the window, the
+/// newest at the bottom. They go on top of the post effects, so they stay
+/// sharp and readable.
+pub fn draw_messages(target: &mut Target, messages: &[Message], width: u32, height: u32) {
+    if messages.is_empty() {
+        return;
+    }
+
+    target.push();
+    target.shape_mode(ShapeMode::TopLeft);
+    target.fill("#ffffff");
+    let left = width as f32 / -2.0 + 20.0;
+    let bottom = height as f32 / -2.0 + 20.0;
+    for (i, message) in messages.iter().rev().enumerate() {
+        target.text(
+            &message.text,
+            Vec2::new(left, bottom + (i + 1) as f32 * 24.0),
+        );
+    }
+    target.pop();
+}
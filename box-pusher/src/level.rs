@@ -1,112 +1,955 @@
 use duku::Vec2;
+use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
+use crate::components::Color;
+use crate::components::Direction;
+use crate::components::PlateMode;
 use crate::error::Result;
+use crate::resources::Rules;
 use crate::world::World;
+use crate::xsb;
 
-pub fn load(world: &mut World, path: impl AsRef<Path>, level_name: impl AsRef<str>) -> Result<()> {
-    let l_name = level_name.as_ref();
+#[derive(Clone, PartialEq)]
+pub struct Level {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    pub grid_size: i32,
+    pub rules: Rules,
+    // music track name
+    pub music: Option<String>,
+    pub tiles: Vec<Tile>,
+    pub instances: Vec<Instance>,
+    // render layers, top first
+    pub layers: Vec<String>,
+    // things in the file the game does not know about
+    pub unknown: Vec<String>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Tile {
+    pub layer: TileLayer,
+    pub x: i32,
+    pub y: i32,
+    pub tileset: String,
+    pub part_pos: Vec2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TileLayer {
+    Collisions,
+    Background,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Instance {
+    pub iid: Option<String>,
+    pub kind: InstanceKind,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum InstanceKind {
+    Player,
+    Box(Option<Color>),
+    Goal(Option<Color>),
+    PressurePlate { mode: PlateMode, doors: Vec<String> },
+    Door { open: bool },
+    Teleporter { target: Option<String> },
+    OneWay(Direction),
+    Conveyor(Direction),
+}
+
+pub fn spawn(world: &mut World, level: &Level) -> Result<()> {
+    world.set_rules(level.rules);
+    world.set_layers(&level.layers);
+
+    // a missing track is not worth losing the level over, but the last
+    // level's track should not play on either
+    let music = world.play_music(level.music.as_deref()).or_else(|err| {
+        println!("{}", err);
+        world.play_music(None)
+    });
+    if let Err(err) = music {
+        println!("{}", err);
+    }
+
+    // spawn tiles
+    let part_size = Vec2::new(level.grid_size as f32, level.grid_size as f32);
+    for tile in &level.tiles {
+        match tile.layer {
+            TileLayer::Collisions => {
+                world.spawn_wall(&tile.tileset, tile.x, tile.y, tile.part_pos, part_size)
+            }
+            TileLayer::Background => {
+                world.spawn_floor(&tile.tileset, tile.x, tile.y, tile.part_pos, part_size)
+            }
+        }
+    }
+
+    // entity references get resolved after everything is spawned
+    let mut iids = HashMap::new();
+    let mut plate_links = vec![];
+    let mut teleporter_links = vec![];
+
+    // spawn entities
+    for instance in &level.instances {
+        let (x, y) = (instance.x, instance.y);
+        let entity = match &instance.kind {
+            InstanceKind::Player => world.spawn_player(x, y),
+            InstanceKind::Box(color) => world.spawn_box(x, y, *color),
+            InstanceKind::Goal(color) => world.spawn_goal(x, y, *color),
+            InstanceKind::PressurePlate { mode, doors } => {
+                let plate = world.spawn_plate(x, y, *mode);
+                plate_links.push((plate, doors));
+                plate
+            }
+            InstanceKind::Teleporter { target } => {
+                let teleporter = world.spawn_teleporter(x, y);
+                teleporter_links.push((teleporter, target));
+                teleporter
+            }
+            InstanceKind::OneWay(direction) => world.spawn_oneway(x, y, *direction),
+            InstanceKind::Conveyor(direction) => world.spawn_conveyor(x, y, *direction),
+            InstanceKind::Door { open } => world.spawn_door(x, y, *open),
+        };
+
+        if let Some(iid) = &instance.iid {
+            iids.insert(iid.as_str(), entity);
+        }
+    }
+
+    // link plates to doors
+    for (plate, refs) in plate_links {
+        let doors = refs
+            .iter()
+            .map(|r| iids.get(r.as_str()).copied().ok_or("bad entity reference"))
+            .collect::<std::result::Result<_, _>>()?;
+        world.link_plate(plate, doors);
+    }
+
+    // link teleporter pairs
+    for (teleporter, target) in teleporter_links {
+        if let Some(r) = target {
+            let target = iids.get(r.as_str()).ok_or("bad entity reference")?;
+            world.link_teleporters(teleporter, *target);
+        }
+    }
+
+    // restarting goes back here without reading the level again
+    world.save_start();
+
+    Ok(())
+}
+
+/// Reads every level from an LDtk or XSB file.
+pub fn parse_file(path: impl AsRef<Path>) -> Result<Vec<Level>> {
+    parse_each(path)?
+        .into_iter()
+        .map(|(_, level)| level)
+        .collect()
+}
+
+/// Reads every level from an LDtk or XSB file, going on past levels that
+/// cannot be read. Each level comes with its name, for reporting.
+pub fn parse_each(path: impl AsRef<Path>) -> Result<Vec<(String, Result<Level>)>> {
+    let path = path.as_ref();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("ldtk") => parse_all(path),
+        _ => Ok(xsb::read_each(&fs::read_to_string(path)?)),
+    }
+}
+
+pub fn parse_all(path: impl AsRef<Path>) -> Result<Vec<(String, Result<Level>)>> {
     let bytes = fs::read(path)?;
     let json: Value = serde_json::from_slice(&bytes)?;
 
-    // find selected level
     let levels = match &json["levels"] {
         Value::Array(vec) => vec,
         _ => return Err("levels are not present".into()),
     };
-    let level = {
-        let level_val = levels
-            .iter()
-            .find(|v| {
-                let i = match v {
-                    Value::Object(map) => &map["identifier"],
-                    _ => &Value::Null,
-                };
-                matches!(i, Value::String(s) if s == l_name)
-            })
-            .ok_or("level not found")?;
-        as_map(level_val)?
+    Ok(levels
+        .iter()
+        .enumerate()
+        .map(|(i, l)| {
+            let name = match l.get("identifier") {
+                Some(Value::String(name)) => name.clone(),
+                _ => format!("Level{}", i + 1),
+            };
+            (name, as_map(l).and_then(parse_level))
+        })
+        .collect())
+}
+
+fn parse_level(level: &Map<String, Value>) -> Result<Level> {
+    let l_name = as_str(key(level, "identifier"))?;
+
+    // get level rules
+    let defaults = Rules::default();
+    let rules = Rules {
+        pull: match field(level, "Pull") {
+            Some(Value::Bool(b)) => *b,
+            _ => defaults.pull,
+        },
+        chain: match field(level, "Chain") {
+            Some(Value::Null) | None => defaults.chain,
+            Some(value) => as_i32(value)?.max(1) as u32,
+        },
+        strength: match field(level, "Strength") {
+            Some(Value::Null) | None => defaults.strength,
+            Some(value) => Some(as_i32(value)?.max(1) as u32),
+        },
+    };
+
+    let music = match field(level, "Music") {
+        Some(Value::String(s)) if !s.is_empty() => Some(s.to_string()),
+        _ => None,
     };
 
     // get layers out of the level
-    let layers = as_vec(&level["layerInstances"])?;
+    let layers = as_vec(key(level, "layerInstances"))?;
+
+    // get level size
+    let level_width = as_i32(key(level, "pxWid"))?;
+    let level_height = as_i32(key(level, "pxHei"))?;
+    let mut level_grid_size = 16;
 
-    // get level height
-    let level_height = as_i32(&level["pxHei"])?;
+    let mut tiles = vec![];
+    let mut instances = vec![];
+    let mut layer_names = vec![];
+    let mut unknown = vec![];
 
     // iterate over layers
     for layer_val in layers {
         let layer = as_map(layer_val)?;
-        let layer_type = as_str(&layer["__type"])?;
-        let grid_size = as_i32(&layer["__gridSize"])?;
+        let layer_type = as_str(key(layer, "__type"))?;
+        let identifier = as_str(key(layer, "__identifier"))?;
+        let grid_size = as_i32(key(layer, "__gridSize"))?;
+        level_grid_size = grid_size;
+        layer_names.push(identifier.to_string());
 
         // check if is tile or entity layer
         match layer_type {
             "Entities" => {
                 // extract instances
-                let instances = as_vec(&layer["entityInstances"])?;
+                let instance_vals = as_vec(key(layer, "entityInstances"))?;
 
-                for instance_val in instances {
+                for instance_val in instance_vals {
                     let instance = as_map(instance_val)?;
-                    let identifier = as_str(&instance["__identifier"])?;
+                    let identifier = as_str(key(instance, "__identifier"))?;
 
                     // get position
-                    let xy = as_vec(&instance["px"])?;
+                    let xy = as_vec(key(instance, "px"))?;
                     let x = as_i32(xy.get(0).ok_or("no x")?)? / grid_size;
                     let y = (level_height - as_i32(xy.get(1).ok_or("no y")?)?) / grid_size;
 
-                    // spawn entity
-                    match identifier {
-                        "Player" => world.spawn_player(x, y),
-                        "Box" => world.spawn_box(x, y),
-                        _ => {}
-                    }
+                    // read entity
+                    let kind = match identifier {
+                        "Player" => InstanceKind::Player,
+                        "Box" => InstanceKind::Box(color(instance)?),
+                        "Goal" => InstanceKind::Goal(color(instance)?),
+                        "PressurePlate" => InstanceKind::PressurePlate {
+                            mode: match field(instance, "Mode") {
+                                Some(Value::String(s)) if s == "Toggle" => PlateMode::Toggle,
+                                _ => PlateMode::Hold,
+                            },
+                            doors: entity_refs(instance, "Doors")?,
+                        },
+                        "Teleporter" => InstanceKind::Teleporter {
+                            target: entity_refs(instance, "Target")?.into_iter().next(),
+                        },
+                        "OneWay" => InstanceKind::OneWay(direction(instance)?),
+                        "Conveyor" => InstanceKind::Conveyor(direction(instance)?),
+                        "Door" => InstanceKind::Door {
+                            open: matches!(field(instance, "Open"), Some(Value::Bool(true))),
+                        },
+                        _ => {
+                            unknown.push(format!("entity {} at {}, {}", identifier, x, y));
+                            continue;
+                        }
+                    };
+
+                    let iid = match instance.get("iid") {
+                        Some(Value::String(iid)) => Some(iid.to_string()),
+                        _ => None,
+                    };
+
+                    instances.push(Instance { iid, kind, x, y });
                 }
             }
             "Tiles" => {
-                // get identifier
-                let identifier = as_str(&layer["__identifier"])?;
-
                 // get texture name
-                let tex_name = as_str(&layer["__tilesetRelPath"])?;
+                let tex_name = as_str(key(layer, "__tilesetRelPath"))?;
 
                 // extract tiles
-                let tiles = as_vec(&layer["gridTiles"])?;
+                let tile_vals = as_vec(key(layer, "gridTiles"))?;
 
-                // get grid size
-                let grid_size = as_i32(&layer["__gridSize"])?;
+                let tile_layer = match identifier {
+                    "Collisions" => TileLayer::Collisions,
+                    "Background" => TileLayer::Background,
+                    _ => {
+                        unknown.push(format!("tile layer {}", identifier));
+                        continue;
+                    }
+                };
 
-                for tile_val in tiles {
+                for tile_val in tile_vals {
                     let tile = as_map(tile_val)?;
 
                     // get coordinates
-                    let xy = as_vec(&tile["px"])?;
-                    let uv = as_vec(&tile["src"])?;
+                    let xy = as_vec(key(tile, "px"))?;
+                    let uv = as_vec(key(tile, "src"))?;
                     let x = as_i32(xy.get(0).ok_or("no x")?)? / grid_size;
                     let y = (level_height - as_i32(xy.get(1).ok_or("no y")?)?) / grid_size;
                     let u = as_i32(uv.get(0).ok_or("no u")?)?;
                     let v = as_i32(uv.get(1).ok_or("no v")?)?;
 
-                    let part_pos = Vec2::new(u as f32, v as f32);
-                    let part_size = Vec2::new(grid_size as f32, grid_size as f32);
-
-                    match identifier {
-                        "Collisions" => world.spawn_wall(tex_name, x, y, part_pos, part_size),
-                        "Background" => world.spawn_floor(tex_name, x, y, part_pos, part_size),
-                        _ => {}
-                    }
+                    tiles.push(Tile {
+                        layer: tile_layer,
+                        x,
+                        y,
+                        tileset: tex_name.to_string(),
+                        part_pos: Vec2::new(u as f32, v as f32),
+                    });
                 }
             }
-            _ => return Err("invalid layer type".into()),
+            _ => unknown.push(format!("{} layer {}", layer_type, identifier)),
+        }
+    }
+
+    Ok(Level {
+        name: l_name.to_string(),
+        width: level_width / level_grid_size,
+        height: level_height / level_grid_size,
+        grid_size: level_grid_size,
+        rules,
+        music,
+        tiles,
+        instances,
+        layers: layer_names,
+        unknown,
+    })
+}
+
+/// Layer order used by levels that do not come from LDtk.
+pub fn default_layers() -> Vec<String> {
+    LAYERS.iter().map(|l| l.0.to_string()).collect()
+}
+
+/// Writes levels out as an LDtk project, so they can be opened in the editor.
+/// Tileset paths stay relative to the project file.
+pub fn save(path: impl AsRef<Path>, levels: &[Level]) -> Result<()> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let grid_size = levels.first().map(|l| l.grid_size).unwrap_or(16);
+    let mut iids = 0;
+    let mut next_iid = || {
+        iids += 1;
+        format!("00000000-0000-4000-8000-{:012x}", iids)
+    };
+
+    // every tile layer can only use one tileset
+    let mut tilesets: Vec<&str> = vec![];
+    let mut layer_tilesets = HashMap::new();
+    for tile in levels.iter().flat_map(|l| &l.tiles) {
+        if !tilesets.contains(&tile.tileset.as_str()) {
+            tilesets.push(&tile.tileset);
+        }
+        let tileset = layer_tilesets.entry(tile.layer).or_insert(&tile.tileset);
+        if *tileset != &tile.tileset {
+            return Err("layer uses more than one tileset".into());
+        }
+    }
+
+    // tileset definitions
+    let mut tileset_sizes = HashMap::new();
+    let mut tileset_defs = vec![];
+    for (i, tileset) in tilesets.iter().enumerate() {
+        let (width, height) = png_size(&dir.join(tileset)).unwrap_or_else(|| {
+            // fall back to the area the tiles use
+            levels
+                .iter()
+                .flat_map(|l| &l.tiles)
+                .filter(|t| t.tileset == *tileset)
+                .fold((grid_size, grid_size), |(w, h), t| {
+                    (
+                        w.max(t.part_pos.x as i32 + grid_size),
+                        h.max(t.part_pos.y as i32 + grid_size),
+                    )
+                })
+        });
+        tileset_sizes.insert(*tileset, width);
+
+        let stem = Path::new(tileset)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("Tileset");
+        tileset_defs.push(json!({
+            "__cWid": width / grid_size,
+            "__cHei": height / grid_size,
+            "identifier": to_identifier(stem),
+            "uid": TILESET_UID + i as i32,
+            "relPath": tileset,
+            "pxWid": width,
+            "pxHei": height,
+            "tileGridSize": grid_size,
+            "spacing": 0,
+            "padding": 0,
+            "tags": [],
+            "tagsSourceEnumUid": null,
+            "enumTags": [],
+            "customData": [],
+            "savedSelections": [],
+            "cachedPixelData": null,
+        }));
+    }
+    let tileset_uid = |name: &str| {
+        tilesets
+            .iter()
+            .position(|t| *t == name)
+            .map(|i| TILESET_UID + i as i32)
+    };
+
+    // layer definitions
+    let layer_defs: Vec<_> = LAYERS
+        .iter()
+        .map(|(name, kind, uid, layer)| {
+            let tileset = layer
+                .and_then(|l| layer_tilesets.get(&l))
+                .and_then(|t| tileset_uid(t));
+            json!({
+                "__type": kind,
+                "identifier": name,
+                "type": kind,
+                "uid": uid,
+                "gridSize": grid_size,
+                "guideGridWid": 0,
+                "guideGridHei": 0,
+                "displayOpacity": 1,
+                "inactiveOpacity": 1,
+                "hideInList": false,
+                "hideFieldsWhenInactive": false,
+                "pxOffsetX": 0,
+                "pxOffsetY": 0,
+                "parallaxFactorX": 0,
+                "parallaxFactorY": 0,
+                "parallaxScaling": true,
+                "requiredTags": [],
+                "excludedTags": [],
+                "intGridValues": [],
+                "autoRuleGroups": [],
+                "autoSourceLayerDefUid": null,
+                "tilesetDefUid": tileset,
+                "tilePivotX": 0,
+                "tilePivotY": 0,
+            })
+        })
+        .collect();
+
+    // entity and enum definitions
+    let enum_defs: Vec<_> = ENUMS
+        .iter()
+        .map(|(name, uid, values)| {
+            json!({
+                "identifier": name,
+                "uid": uid,
+                "values": values
+                    .iter()
+                    .map(|v| json!({ "id": v, "tileId": null, "color": 0, "__tileSrcRect": null }))
+                    .collect::<Vec<_>>(),
+                "iconTilesetUid": null,
+                "externalRelPath": null,
+                "externalFileChecksum": null,
+                "tags": [],
+            })
+        })
+        .collect();
+    let entity_defs: Vec<_> = ENTITIES
+        .iter()
+        .map(|(name, uid, color, fields)| {
+            json!({
+                "identifier": name,
+                "uid": uid,
+                "tags": [],
+                "width": grid_size,
+                "height": grid_size,
+                "resizableX": false,
+                "resizableY": false,
+                "keepAspectRatio": false,
+                "tileOpacity": 1,
+                "fillOpacity": 1,
+                "lineOpacity": 1,
+                "hollow": false,
+                "color": color,
+                "renderMode": "Rectangle",
+                "showName": true,
+                "tilesetId": null,
+                "tileRenderMode": "FitInside",
+                "tileRect": null,
+                "nineSliceBorders": [],
+                "maxCount": 0,
+                "limitScope": "PerLevel",
+                "limitBehavior": "MoveLastOne",
+                "pivotX": 0,
+                "pivotY": 0,
+                "fieldDefs": fields
+                    .iter()
+                    .map(|(field, kind, ty, _)| field_def(field, kind, ty, field_uid(*uid, field)))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    // levels
+    let mut world_x = 0;
+    let mut level_vals = vec![];
+    for (i, level) in levels.iter().enumerate() {
+        let width = level.width * level.grid_size;
+        let height = level.height * level.grid_size;
+        let level_iid = next_iid();
+        let entities_iid = next_iid();
+
+        // turn level coordinates back into LDtk pixels
+        let grid = |x: i32, y: i32| json!([x, level.height - y]);
+        let px = |x: i32, y: i32| json!([x * level.grid_size, height - y * level.grid_size]);
+        let entity_ref = |iid: &str| {
+            json!({
+                "entityIid": iid,
+                "layerIid": entities_iid,
+                "levelIid": level_iid,
+            })
+        };
+
+        // entity layer
+        let mut entity_vals = vec![];
+        for instance in &level.instances {
+            let (name, uid, smart_color, _) = ENTITIES
+                .iter()
+                .find(|e| e.0 == instance.kind.identifier())
+                .expect("bad entity def");
+            let field = |field: &str, value: Value| {
+                let (_, _, ty, _) = fields(*uid)
+                    .iter()
+                    .find(|f| f.0 == field)
+                    .expect("bad field def");
+                field_instance(field, ty, field_uid(*uid, field), value)
+            };
+
+            let fields = match &instance.kind {
+                InstanceKind::Player => vec![],
+                InstanceKind::Box(c) | InstanceKind::Goal(c) => {
+                    vec![field("Color", json!(c.map(|c| format!("{:?}", c))))]
+                }
+                InstanceKind::PressurePlate { mode, doors } => vec![
+                    field("Mode", json!(format!("{:?}", mode))),
+                    field(
+                        "Doors",
+                        Value::Array(doors.iter().map(|d| entity_ref(d)).collect()),
+                    ),
+                ],
+                InstanceKind::Door { open } => vec![field("Open", json!(open))],
+                InstanceKind::Teleporter { target } => vec![field(
+                    "Target",
+                    target.as_deref().map(&entity_ref).unwrap_or(Value::Null),
+                )],
+                InstanceKind::OneWay(d) | InstanceKind::Conveyor(d) => {
+                    vec![field("Direction", json!(format!("{:?}", d)))]
+                }
+            };
+
+            entity_vals.push(json!({
+                "__identifier": name,
+                "__grid": grid(instance.x, instance.y),
+                "__pivot": [0, 0],
+                "__tags": [],
+                "__tile": null,
+                "__smartColor": smart_color,
+                "iid": instance.iid.clone().unwrap_or_else(&mut next_iid),
+                "width": level.grid_size,
+                "height": level.grid_size,
+                "defUid": uid,
+                "px": px(instance.x, instance.y),
+                "fieldInstances": fields,
+            }));
         }
+
+        let mut layer_vals = vec![];
+        for (name, kind, uid, layer) in &LAYERS {
+            let layer_iid = match layer {
+                Some(_) => next_iid(),
+                None => entities_iid.clone(),
+            };
+            let tileset = layer.and_then(|l| layer_tilesets.get(&l)).copied();
+
+            // tile layer
+            let tile_vals: Vec<_> = level
+                .tiles
+                .iter()
+                .filter(|t| Some(t.layer) == *layer)
+                .map(|t| {
+                    let (u, v) = (t.part_pos.x as i32, t.part_pos.y as i32);
+                    let columns = tileset_sizes[t.tileset.as_str()] / level.grid_size;
+                    let cell = t.x + (level.height - t.y) * level.width;
+                    json!({
+                        "px": px(t.x, t.y),
+                        "src": [u, v],
+                        "f": 0,
+                        "t": u / level.grid_size + v / level.grid_size * columns,
+                        "d": [cell],
+                        "a": 1,
+                    })
+                })
+                .collect();
+
+            layer_vals.push(json!({
+                "__identifier": name,
+                "__type": kind,
+                "__cWid": level.width,
+                "__cHei": level.height,
+                "__gridSize": level.grid_size,
+                "__opacity": 1,
+                "__pxTotalOffsetX": 0,
+                "__pxTotalOffsetY": 0,
+                "__tilesetDefUid": tileset.and_then(|t| tileset_uid(t)),
+                "__tilesetRelPath": tileset,
+                "iid": layer_iid,
+                "levelId": LEVEL_UID + i as i32,
+                "layerDefUid": uid,
+                "pxOffsetX": 0,
+                "pxOffsetY": 0,
+                "visible": true,
+                "optionalRules": [],
+                "intGridCsv": [],
+                "autoLayerTiles": [],
+                "seed": 0,
+                "overrideTilesetUid": null,
+                "gridTiles": tile_vals,
+                "entityInstances": if layer.is_none() { entity_vals.clone() } else { vec![] },
+            }));
+        }
+
+        // level rules
+        let rules = level.rules;
+        let level_fields: Vec<_> = LEVEL_FIELDS
+            .iter()
+            .map(|(name, _, ty, uid)| {
+                let value = match *name {
+                    "Pull" => json!(rules.pull),
+                    "Chain" => json!(rules.chain),
+                    "Strength" => json!(rules.strength),
+                    _ => json!(level.music),
+                };
+                field_instance(name, ty, *uid, value)
+            })
+            .collect();
+
+        level_vals.push(json!({
+            "identifier": to_identifier(&level.name),
+            "iid": level_iid,
+            "uid": LEVEL_UID + i as i32,
+            "worldX": world_x,
+            "worldY": 0,
+            "worldDepth": 0,
+            "pxWid": width,
+            "pxHei": height,
+            "__bgColor": BG_COLOR,
+            "bgColor": null,
+            "useAutoIdentifier": false,
+            "bgRelPath": null,
+            "bgPos": null,
+            "bgPivotX": 0.5,
+            "bgPivotY": 0.5,
+            "__smartColor": "#ADADB5",
+            "__bgPos": null,
+            "externalRelPath": null,
+            "fieldInstances": level_fields,
+            "layerInstances": layer_vals,
+            "__neighbours": [],
+        }));
+        world_x += width + grid_size * 2;
     }
 
+    let level_field_defs: Vec<_> = LEVEL_FIELDS
+        .iter()
+        .map(|(name, kind, ty, uid)| field_def(name, kind, ty, *uid))
+        .collect();
+
+    let project = json!({
+        "__header__": {
+            "fileType": "LDtk Project JSON",
+            "app": "LDtk",
+            "doc": "https://ldtk.io/json",
+            "schema": "https://ldtk.io/files/JSON_SCHEMA.json",
+            "appAuthor": "Sebastien 'deepnight' Benard",
+            "appVersion": LDTK_VERSION,
+            "url": "https://ldtk.io",
+        },
+        "iid": next_iid(),
+        "jsonVersion": LDTK_VERSION,
+        "appBuildId": 0,
+        "nextUid": LEVEL_UID + levels.len() as i32,
+        "identifierStyle": "Capitalize",
+        "worldLayout": "LinearHorizontal",
+        "worldGridWidth": 256,
+        "worldGridHeight": 256,
+        "defaultLevelWidth": levels.first().map(|l| l.width * l.grid_size).unwrap_or(256),
+        "defaultLevelHeight": levels.first().map(|l| l.height * l.grid_size).unwrap_or(256),
+        "defaultPivotX": 0,
+        "defaultPivotY": 0,
+        "defaultGridSize": grid_size,
+        "bgColor": BG_COLOR,
+        "defaultLevelBgColor": BG_COLOR,
+        "minifyJson": false,
+        "externalLevels": false,
+        "exportTiled": false,
+        "simplifiedExport": false,
+        "imageExportMode": "None",
+        "exportLevelBg": true,
+        "pngFilePattern": null,
+        "backupOnSave": false,
+        "backupLimit": 10,
+        "levelNamePattern": "Level_%idx",
+        "tutorialDesc": null,
+        "flags": [],
+        "defs": {
+            "layers": layer_defs,
+            "entities": entity_defs,
+            "tilesets": tileset_defs,
+            "enums": enum_defs,
+            "externalEnums": [],
+            "levelFields": level_field_defs,
+        },
+        "levels": level_vals,
+        "worlds": [],
+        "dummyWorldIid": next_iid(),
+    });
+
+    fs::write(path, serde_json::to_vec_pretty(&project)?)?;
     Ok(())
 }
 
+impl InstanceKind {
+    fn identifier(&self) -> &'static str {
+        match self {
+            Self::Player => "Player",
+            Self::Box(_) => "Box",
+            Self::Goal(_) => "Goal",
+            Self::PressurePlate { .. } => "PressurePlate",
+            Self::Door { .. } => "Door",
+            Self::Teleporter { .. } => "Teleporter",
+            Self::OneWay(_) => "OneWay",
+            Self::Conveyor(_) => "Conveyor",
+        }
+    }
+}
+
+const LDTK_VERSION: &str = "1.1.3";
+const BG_COLOR: &str = "#7F8093";
+const TILESET_UID: i32 = 100;
+const LEVEL_UID: i32 = 200;
+
+// identifier, type, uid and which tiles go in it
+const LAYERS: [(&str, &str, i32, Option<TileLayer>); 3] = [
+    ("Entities", "Entities", 1, None),
+    ("Collisions", "Tiles", 2, Some(TileLayer::Collisions)),
+    ("Background", "Tiles", 3, Some(TileLayer::Background)),
+];
+
+const ENUMS: [(&str, i32, &[&str]); 3] = [
+    ("Color", 10, &["Red", "Green", "Blue", "Yellow"]),
+    ("Direction", 11, &["Up", "Down", "Left", "Right"]),
+    ("PlateMode", 12, &["Hold", "Toggle"]),
+];
+
+// field identifier, field type, value type and uid or index
+type FieldDef = (&'static str, &'static str, &'static str, i32);
+
+const COLOR_FIELDS: &[FieldDef] = &[("Color", "F_Enum(10)", "LocalEnum.Color", 0)];
+const DIRECTION_FIELDS: &[FieldDef] = &[("Direction", "F_Enum(11)", "LocalEnum.Direction", 0)];
+const PLATE_FIELDS: &[FieldDef] = &[
+    ("Mode", "F_Enum(12)", "LocalEnum.PlateMode", 0),
+    ("Doors", "F_EntityRef", "Array<EntityRef>", 1),
+];
+const DOOR_FIELDS: &[FieldDef] = &[("Open", "F_Bool", "Bool", 0)];
+const TELEPORTER_FIELDS: &[FieldDef] = &[("Target", "F_EntityRef", "EntityRef", 0)];
+
+const LEVEL_FIELDS: [FieldDef; 4] = [
+    ("Pull", "F_Bool", "Bool", 90),
+    ("Chain", "F_Int", "Int", 91),
+    ("Strength", "F_Int", "Int", 92),
+    ("Music", "F_String", "String", 93),
+];
+
+// identifier, uid, editor color and fields
+const ENTITIES: [(&str, i32, &str, &[FieldDef]); 8] = [
+    ("Player", 20, "#24C611", &[]),
+    ("Box", 21, "#E1F12C", COLOR_FIELDS),
+    ("Goal", 22, "#F1902C", COLOR_FIELDS),
+    ("PressurePlate", 23, "#8C8C8C", PLATE_FIELDS),
+    ("Door", 24, "#8A4B2C", DOOR_FIELDS),
+    ("Teleporter", 25, "#A32CF1", TELEPORTER_FIELDS),
+    ("OneWay", 26, "#2C9BF1", DIRECTION_FIELDS),
+    ("Conveyor", 27, "#2CF1C8", DIRECTION_FIELDS),
+];
+
+fn fields(entity_uid: i32) -> &'static [FieldDef] {
+    ENTITIES
+        .iter()
+        .find(|e| e.1 == entity_uid)
+        .map(|e| e.3)
+        .unwrap_or(&[])
+}
+
+// entity field uids are offset by the entity uid
+fn field_uid(entity_uid: i32, name: &str) -> i32 {
+    let index = fields(entity_uid)
+        .iter()
+        .find(|f| f.0 == name)
+        .map(|f| f.3)
+        .unwrap_or(0);
+    30 + (entity_uid - 20) * 5 + index
+}
+
+fn field_def(name: &str, kind: &str, ty: &str, uid: i32) -> Value {
+    json!({
+        "identifier": name,
+        "__type": ty,
+        "uid": uid,
+        "type": kind,
+        "isArray": ty.starts_with("Array"),
+        "canBeNull": kind != "F_Bool",
+        "arrayMinLength": null,
+        "arrayMaxLength": null,
+        "editorDisplayMode": "ValueOnly",
+        "editorDisplayPos": "Above",
+        "editorAlwaysShow": false,
+        "editorCutLongValues": true,
+        "editorTextSuffix": null,
+        "editorTextPrefix": null,
+        "useForSmartColor": false,
+        "min": null,
+        "max": null,
+        "regex": null,
+        "acceptFileTypes": null,
+        "defaultOverride": null,
+        "textLanguageMode": null,
+        "symmetricalRef": false,
+        "autoChainRef": true,
+        "allowOutOfLevelRef": false,
+        "allowedRefs": "OnlySame",
+        "allowedRefTags": [],
+        "tilesetUid": null,
+    })
+}
+
+fn field_instance(name: &str, ty: &str, uid: i32, value: Value) -> Value {
+    // the editor reads these instead of __value
+    let editor_value = |v: &Value| match v {
+        Value::Bool(b) => Some(json!({ "id": "V_Bool", "params": [b] })),
+        Value::Number(n) => Some(json!({ "id": "V_Int", "params": [n] })),
+        Value::String(s) => Some(json!({ "id": "V_String", "params": [s] })),
+        Value::Object(r) => Some(json!({ "id": "V_String", "params": [r["entityIid"]] })),
+        _ => None,
+    };
+    let editor_values: Vec<_> = match &value {
+        Value::Array(vec) => vec.iter().filter_map(editor_value).collect(),
+        v => editor_value(v).into_iter().collect(),
+    };
+    json!({
+        "__identifier": name,
+        "__type": ty,
+        "__value": value,
+        "__tile": null,
+        "defUid": uid,
+        "realEditorValues": editor_values,
+    })
+}
+
+// LDtk identifiers are letters, digits and underscores
+fn to_identifier(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !id.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        id.insert(0, '_');
+    }
+    id
+}
+
+fn png_size(path: &Path) -> Option<(i32, i32)> {
+    let bytes = fs::read(path).ok()?;
+    if bytes.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let size = |b: &[u8]| i32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    Some((size(bytes.get(16..20)?), size(bytes.get(20..24)?)))
+}
+
+/// Looks up a key, missing ones read as null so they give an error instead
+/// of a panic.
+fn key<'a>(map: &'a Map<String, Value>, name: &str) -> &'a Value {
+    map.get(name).unwrap_or(&Value::Null)
+}
+
+fn field<'a>(instance: &'a Map<String, Value>, name: &str) -> Option<&'a Value> {
+    match instance.get("fieldInstances") {
+        Some(Value::Array(fields)) => fields
+            .iter()
+            .filter_map(|f| as_map(f).ok())
+            .find(|f| matches!(key(f, "__identifier"), Value::String(s) if s == name))
+            .map(|f| key(f, "__value")),
+        _ => None,
+    }
+}
+
+fn color(instance: &Map<String, Value>) -> Result<Option<Color>> {
+    match field(instance, "Color") {
+        Some(Value::Null) | None => Ok(None),
+        Some(value) => match as_str(value)? {
+            "Red" => Ok(Some(Color::Red)),
+            "Green" => Ok(Some(Color::Green)),
+            "Blue" => Ok(Some(Color::Blue)),
+            "Yellow" => Ok(Some(Color::Yellow)),
+            _ => Err("invalid color".into()),
+        },
+    }
+}
+
+fn direction(instance: &Map<String, Value>) -> Result<Direction> {
+    match field(instance, "Direction") {
+        Some(value) => match as_str(value)? {
+            "Up" => Ok(Direction::Up),
+            "Down" => Ok(Direction::Down),
+            "Left" => Ok(Direction::Left),
+            "Right" => Ok(Direction::Right),
+            _ => Err("invalid direction".into()),
+        },
+        None => Err("no direction".into()),
+    }
+}
+
+fn entity_refs(instance: &Map<String, Value>, name: &str) -> Result<Vec<String>> {
+    let refs = match field(instance, name) {
+        Some(Value::Array(vec)) => vec.iter().collect(),
+        Some(Value::Null) | None => vec![],
+        Some(value) => vec![value],
+    };
+
+    refs.into_iter()
+        .map(|r| -> Result<String> {
+            let r = as_map(r)?;
+            Ok(as_str(key(r, "entityIid"))?.to_string())
+        })
+        .collect()
+}
+
 fn as_i32(value: &Value) -> Result<i32> {
     match value {
         Value::Number(n) => {
@@ -137,3 +980,95 @@ fn as_map(value: &Value) -> Result<&Map<String, Value>> {
         _ => Err("invalid map".into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn spawn_with_unknown_music() {
+        let mut level = xsb::read_each("#####\n#@$.#\n#####\n").remove(0).1.unwrap();
+        level.music = Some("missing".to_string());
+
+        let config = Config {
+            mute: true,
+            ..Default::default()
+        };
+        let mut world = World::new(&config).unwrap();
+        assert!(spawn(&mut world, &level).is_ok());
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let mut level = xsb::read_each("######\n#@R r#\n#    #\n######\n")
+            .remove(0)
+            .1
+            .unwrap();
+        let mut add = |iid: &str, kind, x, y| {
+            level.instances.push(Instance {
+                iid: Some(iid.to_string()),
+                kind,
+                x,
+                y,
+            })
+        };
+        add(
+            "plate",
+            InstanceKind::PressurePlate {
+                mode: PlateMode::Hold,
+                doors: vec!["door".to_string()],
+            },
+            1,
+            2,
+        );
+        // the second row is empty floor, rows count up from the bottom
+        add("door", InstanceKind::Door { open: false }, 2, 2);
+        add(
+            "a",
+            InstanceKind::Teleporter {
+                target: Some("b".to_string()),
+            },
+            3,
+            2,
+        );
+        add(
+            "b",
+            InstanceKind::Teleporter {
+                target: Some("a".to_string()),
+            },
+            4,
+            2,
+        );
+        // saving gives every instance an iid, so give them one up front
+        for (i, instance) in level.instances.iter_mut().enumerate() {
+            instance.iid.get_or_insert_with(|| format!("instance{}", i));
+        }
+        let colors = level
+            .instances
+            .iter()
+            .filter(|i| {
+                matches!(
+                    i.kind,
+                    InstanceKind::Box(Some(_)) | InstanceKind::Goal(Some(_))
+                )
+            })
+            .count();
+        assert_eq!(colors, 2);
+
+        let path = std::env::temp_dir().join("box-pusher-round-trip.ldtk");
+        save(&path, std::slice::from_ref(&level)).unwrap();
+        let mut levels = parse_all(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(levels.len(), 1);
+        let (name, loaded) = levels.remove(0);
+        assert_eq!(name, level.name);
+        // tiles come back grouped by layer, their order means nothing
+        let mut loaded = loaded.unwrap();
+        let by_cell = |t: &Tile| (t.layer == TileLayer::Background, t.x, t.y);
+        loaded.tiles.sort_by_key(by_cell);
+        level.tiles.sort_by_key(by_cell);
+        assert!(loaded == level);
+    }
+}
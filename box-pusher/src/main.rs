@@ -1,104 +1,427 @@
 mod macros;
 
+mod audio;
+mod capture;
 mod components;
+mod config;
+mod crt;
+mod editor;
 mod error;
+mod generator;
+mod hint;
+mod hud;
 mod level;
+mod menu;
+mod music;
+mod particles;
+mod postfx;
+mod random;
+mod render;
+mod replay;
 mod resources;
+mod settings;
+mod solver;
 mod systems;
+mod tools;
+mod validate;
+mod viewport;
 mod world;
+mod xsb;
 
 use duku::glsl::Metadata;
+use duku::window::Key;
 use duku::Duku;
-use duku::Filter;
 use duku::Rgb;
-use duku::Wrap;
 use gilrs::Gilrs;
+use std::env;
+use std::path::Path;
+use std::process;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
+use capture::Recorder;
+use config::Command;
+use config::Config;
+use crt::CrtSettings;
+use editor::Editor;
 use error::Result;
-use systems::AnimateSystem;
+use generator::Params;
+use hint::Hint;
+use level::Level;
+use menu::OptionsMenu;
+use postfx::Pipeline;
+use render::GpuRenderer;
+use render::Image;
+use render::SoftwareRenderer;
+use replay::Replay;
+use replay::ReplayWriter;
+use replay::Tick;
+use resources::Button;
+use settings::Settings;
+use solver::Board;
+use solver::Puzzle;
 use systems::DrawSystem;
 use systems::InputSystem;
-use systems::MoveSystem;
+use viewport::Viewport;
 use world::World;
 
 fn main() -> Result<()> {
-    let tile_size = 16;
-    let view_width = tile_size * 10;
-    let view_height = tile_size * 9;
-    let window_width = view_width * 5;
-    let window_height = view_height * 5;
+    let mut config = Config::from_args(env::args().skip(1))?;
 
-    let (mut duku, window) = Duku::windowed(window_width, window_height);
+    // headless tools
+    match &config.command {
+        Command::Play => (),
+        Command::Help => {
+            println!("{}", config::USAGE);
+            return Ok(());
+        }
+        command => {
+            let passed = tools::run(command)?;
+            process::exit(if passed { 0 } else { 1 });
+        }
+    }
+
+    // replays bring their own level and seed
+    let replay = match &config.replay {
+        Some(path) => Some(Replay::load(path)?),
+        None => None,
+    };
+    if let Some(replay) = &replay {
+        if replay.version != env!("CARGO_PKG_VERSION") {
+            println!(
+                "replay was recorded on version {}, it may play differently",
+                replay.version
+            );
+        }
+        config.level_file = replay.level_file.clone();
+        config.level_name = Some(replay.level.clone());
+        config.seed = Some(replay.seed);
+    }
+
+    let tile_size = config.tile_size;
+    let view_width = config.view_width();
+    let view_height = config.view_height();
+    let scale = config.scale;
+    let window_width = view_width * scale;
+    let window_height = view_height * scale;
+
+    let (mut duku, window) = Duku::builder()
+        .build_window(window_width, window_height)
+        .title("box-pusher")
+        .resizable()
+        .build()?;
     let mut gilrs = Gilrs::new().expect("bad gilrs");
 
-    let shader_path = "assets/crt.glsl";
-    let mut crt_shader = duku.create_shader_glsl(shader_path)?;
-    let mut meta = Metadata::new(shader_path)?;
+    let mut viewport = Viewport::new(
+        config.scale_mode,
+        (view_width, view_height),
+        (window_width, window_height),
+        tile_size,
+    );
+
     let canvas = duku.create_canvas(view_width, view_height);
-    let material = duku.create_material();
-
-    // set up material
-    {
-        let mut m = material.write();
-        m.a.x = canvas.read().shader_index(0).expect("no texture") as f32;
-        m.b.x = 6.0;
-        m.b.y = 6.0;
-        m.c.x = 0.5;
-        m.d.x = view_width as f32;
-        m.d.y = view_height as f32;
+    let mut pipeline = Pipeline::load(&mut duku, "assets/postfx.json", &viewport)?;
+
+    // settings come from the settings file, the command line can
+    // override the crt effect until it is changed in the menu. Only
+    // what the menu changes is saved, never the override.
+    let settings_path = "assets/settings.json";
+    let mut settings_meta = Metadata::new(settings_path).ok();
+    let mut saved = Settings::load(settings_path).unwrap_or_else(|err| {
+        println!("{}", err);
+        Settings::default()
+    });
+    let mut crt_override = config.crt;
+    let mut settings = saved;
+    if let Some(crt) = crt_override {
+        settings.crt = crt;
     }
+    let mut menu = OptionsMenu::default();
+    let mut unsaved = false;
 
-    let mut world = World::new()?;
+    pipeline.set_crt(&settings.crt);
+
+    let mut world = World::new(&config)?;
 
     // load sprites
-    world.add_sprite(&mut duku, "assets/player.png")?;
-    world.add_sprite(&mut duku, "assets/floor.png")?;
-    world.add_sprite(&mut duku, "assets/wall.png")?;
-    world.add_sprite(&mut duku, "assets/box.png")?;
+    let textures = render::load_textures(&mut duku, "assets")?;
 
     // load sounds
-    world.add_sound("assets/slurp.mp3");
+    world.load_sounds("assets/sounds.json")?;
+    world.load_music("assets/music.json")?;
+    world.set_volume(settings.volume)?;
 
     // load level
-    level::load(&mut world, "assets/world.ldtk", "Test")?;
+    let levels = level::parse_file(&config.level_file)?;
+    let level = match &config.level_name {
+        Some(name) => levels.into_iter().find(|l| &l.name == name),
+        None => levels.into_iter().next(),
+    }
+    .ok_or("level not found")?;
+    level::spawn(&mut world, &level)?;
+    let mut replay_writer = match &config.save_replay {
+        Some(path) => Some(ReplayWriter::create(
+            path,
+            &config.level_file,
+            &level.name,
+            world.seed(),
+        )?),
+        None => None,
+    };
+    let mut playback = replay.map(|r| r.into_ticks());
+    let mut editor = Editor::new(level);
+
+    // duku can't read canvases back, so captures are drawn again on the CPU
+    let mut capture = SoftwareRenderer::new(view_width, view_height, "assets")?;
+    let mut recorder = config.record.map(|seconds| Recorder::new(Some(seconds)));
+    let capture_dir = config.capture_dir.clone();
+    let capture_scale = config.capture_scale;
+
+    let mut fullscreen = config.fullscreen;
+    let mut next_seed = config.seed;
+    let mut was_focused = true;
+    let hint_budget = config.hint_budget;
+    // board the pending hint was asked for, and where it will arrive
+    let mut hint_request: Option<(Board, Receiver<Hint>)> = None;
+    // level being generated after F5
+    let mut level_request: Option<Receiver<Result<Level>>> = None;
 
     window.while_open(move |events| {
-        if meta.is_modified() {
-            match duku.create_shader_glsl(shader_path) {
-                Ok(shader) => crt_shader = shader,
+        if fullscreen {
+            events.set_fullscreen(true);
+            fullscreen = false;
+        }
+
+        // keep the view scaled to the window, F4 cycles the scale mode
+        if let Some((width, height)) = events.resized() {
+            viewport.resize(width, height);
+            pipeline.resize(&mut duku, &viewport);
+        }
+        if events.is_key_typed(Key::F4) {
+            viewport.mode = viewport.mode.next();
+            pipeline.resize(&mut duku, &viewport);
+            println!("scale mode {:?}", viewport.mode);
+        }
+
+        match pipeline.reload(&mut duku) {
+            Ok(true) => pipeline.set_crt(&settings.crt),
+            Ok(false) => (),
+            Err(err) => println!("{}", err),
+        }
+        if settings_meta
+            .as_mut()
+            .map(|m| m.is_modified())
+            .unwrap_or(false)
+        {
+            match Settings::load(settings_path) {
+                Ok(loaded) => {
+                    saved = loaded;
+                    settings = loaded;
+                    if let Some(crt) = crt_override {
+                        settings.crt = crt;
+                    }
+                    pipeline.set_crt(&settings.crt);
+                    if let Err(err) = world.set_volume(settings.volume) {
+                        println!("{}", err);
+                    }
+                }
                 Err(err) => println!("{}", err),
             }
         }
 
-        world.run_system(InputSystem {
-            gilrs: &mut gilrs,
-            events,
-        });
+        let before = settings;
+        if menu.update(events, &mut settings) {
+            if settings.crt != before.crt {
+                saved.crt = settings.crt;
+                crt_override = None;
+            }
+            if settings.volume != before.volume {
+                saved.volume = settings.volume;
+            }
+            pipeline.set_crt(&settings.crt);
+            if let Err(err) = world.set_volume(settings.volume) {
+                println!("{}", err);
+            }
+            unsaved = true;
+        }
 
-        world.run_system(MoveSystem {});
+        // keep changes once the menu is closed
+        if unsaved && !menu.open {
+            if let Err(err) = saved.save(settings_path) {
+                println!("{}", err);
+            }
+            unsaved = false;
+        }
 
-        world.run_system(AnimateSystem {
-            delta_time: duku.delta_time(),
-        });
+        // go quiet while the window is in the background
+        let focused = events.is_focused();
+        if focused != was_focused {
+            if let Err(err) = world.set_muted(!focused) {
+                println!("{}", err);
+            }
+            was_focused = focused;
+        }
+
+        // generate a fresh level and keep a copy of it, off the render loop
+        if events.is_key_typed(Key::F5) && level_request.is_none() {
+            // seeds given on the command line count up from there
+            let seed = next_seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_nanos() as u64)
+                    .unwrap_or(0)
+            });
+            next_seed = next_seed.map(|s| s.wrapping_add(1));
+            world.show_message("generating a level");
+            level_request = Some(generator::request(Params {
+                seed,
+                ..Default::default()
+            }));
+        }
+        let generated = level_request
+            .as_ref()
+            .and_then(|receiver| receiver.try_recv().ok());
+        if let Some(generated) = generated {
+            level_request = None;
+            let generated = generated.and_then(|level| {
+                world.clear();
+                level::spawn(&mut world, &level)?;
+                level::save("assets/generated.ldtk", std::slice::from_ref(&level))?;
+                let text = xsb::write(&level)?;
+                editor.set_level(level);
+                Ok(text)
+            });
+            // replays only cover the level they started on
+            stop_replays(&mut replay_writer, &mut playback);
+            match generated {
+                Ok(text) => println!("{}", text),
+                Err(err) => println!("{}", err),
+            }
+        }
+
+        // the menu takes all input while open
+        if !menu.open {
+            if let Err(err) = editor.update(events, &mut world, &viewport) {
+                println!("{}", err);
+            }
+        }
+
+        if editor.active {
+            stop_replays(&mut replay_writer, &mut playback);
+        }
+
+        // gameplay is paused while editing, replays wait for the menu
+        let mut paused = editor.active || menu.open;
+        let tick = match &mut playback {
+            Some(ticks) if !paused => ticks.next(),
+            _ => None,
+        };
+        match tick {
+            Some(tick) if tick.paused() => paused = true,
+            Some(tick) => world.set_buttons(tick.buttons()),
+            None if !paused => {
+                if playback.take().is_some() {
+                    println!("replay finished");
+                }
+                world.run_system(InputSystem {
+                    gilrs: &mut gilrs,
+                    events,
+                });
+            }
+            None => (),
+        }
+        if let Some(writer) = &mut replay_writer {
+            if let Err(err) = writer.push(Tick::new(&world.buttons(), paused)) {
+                println!("{}", err);
+                replay_writer = None;
+            }
+        }
+
+        world.step(paused, duku.delta_time());
+
+        // ask the solver for a hint, it is dropped if the board changed
+        if !paused && hint_request.is_none() && world.is_typed(Button::Hint) {
+            match Puzzle::from_level(editor.level()) {
+                Ok(puzzle) => {
+                    let boards = world.boards();
+                    if let Some(board) = boards.first().cloned() {
+                        world.show_message("hint: thinking");
+                        hint_request = Some((board, hint::request(puzzle, boards, hint_budget)));
+                    }
+                }
+                Err(err) => world.show_message(format!("hint: not available here, {}", err)),
+            }
+        }
+        let found = hint_request
+            .as_ref()
+            .and_then(|(_, receiver)| receiver.try_recv().ok());
+        if let (Some(found), Some((board, _))) = (found, hint_request.take()) {
+            if world.boards().first() == Some(&board) {
+                found.show(&mut world);
+            }
+        }
+
+        world.set_music_paused(menu.open);
+        if let Err(err) = world.update_audio() {
+            println!("{}", err);
+        }
+
+        // F12 takes a screenshot, F11 starts and stops recording
+        let screenshot = events.is_key_typed(Key::F12);
+        if events.is_key_typed(Key::F11) {
+            recorder = match recorder.take() {
+                Some(recording) => {
+                    save_recording(recording, &capture_dir, capture_scale, settings.crt);
+                    None
+                }
+                None => Some(Recorder::new(None)),
+            };
+        }
+        if screenshot || recorder.is_some() {
+            capture.clear();
+            world.run_system(DrawSystem {
+                renderer: &mut capture,
+                view_width,
+                view_height,
+                tile_size,
+                overlay: None,
+            });
+        }
+        if screenshot {
+            if let Err(err) =
+                save_screenshot(capture.image(), &capture_dir, capture_scale, settings.crt)
+            {
+                println!("{}", err);
+            }
+        }
+        if let Some(recording) = &mut recorder {
+            recording.add(capture.image(), duku.delta_time());
+            if recording.is_done() {
+                if let Some(recording) = recorder.take() {
+                    save_recording(recording, &capture_dir, capture_scale, settings.crt);
+                }
+            }
+        }
 
         duku.begin();
 
         duku.draw_on_canvas(&canvas, None, |t| {
             t.background(Rgb::clear());
             world.run_system(DrawSystem {
-                target: t,
+                renderer: &mut GpuRenderer::new(t, &textures),
                 view_width,
                 view_height,
                 tile_size,
+                overlay: editor.overlay(),
             });
         });
 
-        duku.draw(None, |t| {
-            t.background("#000000");
-            t.filter(Filter::Nearest);
-            t.wrap(Wrap::ClampBorder);
-            t.material(&material);
-            t.surface(&crt_shader);
+        pipeline.draw(&mut duku, &canvas, |t| {
+            let (width, height) = viewport.window;
+            hud::draw_messages(t, &world.messages(), width, height);
+            menu.draw(t, &settings, width, height);
         });
 
         duku.end();
@@ -106,3 +429,43 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Saves the plain canvas, and a scaled up copy through the CRT effect.
+fn save_screenshot(image: &Image, dir: &Path, scale: u32, crt: CrtSettings) -> Result<()> {
+    let path = capture::capture_path(dir, ".png")?;
+    image.save_png(&path)?;
+    println!("saved {}", path.display());
+
+    if crt.enabled {
+        let path = capture::capture_path(dir, "-crt.png")?;
+        capture::finish(image, scale, Some(&crt)).save_png(&path)?;
+        println!("saved {}", path.display());
+    }
+    Ok(())
+}
+
+/// Encodes a recording off the main thread, so the game doesn't stall.
+fn save_recording(recording: Recorder, dir: &Path, scale: u32, crt: CrtSettings) {
+    let path = match capture::capture_path(dir, ".gif") {
+        Ok(path) => path,
+        Err(err) => return println!("{}", err),
+    };
+    println!("saving {}", path.display());
+    thread::spawn(move || match recording.save_gif(&path, scale, Some(&crt)) {
+        Ok(()) => println!("saved {}", path.display()),
+        Err(err) => println!("{}", err),
+    });
+}
+
+/// Stops recording and playing replays, once the level changes.
+fn stop_replays(writer: &mut Option<ReplayWriter>, playback: &mut Option<impl Iterator>) {
+    if let Some(writer) = writer.take() {
+        match writer.finish() {
+            Ok(()) => println!("replay recording stopped, the level changed"),
+            Err(err) => println!("{}", err),
+        }
+    }
+    if playback.take().is_some() {
+        println!("replay stopped, the level changed");
+    }
+}
@@ -0,0 +1,150 @@
+use duku::window::Events;
+use duku::window::Key;
+use duku::ShapeMode;
+use duku::Target;
+use duku::Vec2;
+
+use crate::crt::CrtSettings;
+use crate::crt::MAX_ABERRATION;
+use crate::crt::MAX_BLOOM;
+use crate::crt::MAX_CURVATURE;
+use crate::crt::MAX_SCANLINES;
+use crate::crt::MAX_VIGNETTE;
+use crate::crt::PRESETS;
+use crate::settings::Settings;
+
+// name, step and largest value of each setting
+const SLIDERS: [(&str, f32, f32); 8] = [
+    ("curvature", 0.01, MAX_CURVATURE),
+    ("scanlines", 0.05, MAX_SCANLINES),
+    ("vignette", 0.1, MAX_VIGNETTE),
+    ("bloom", 0.05, MAX_BLOOM),
+    ("aberration", 0.25, MAX_ABERRATION),
+    ("master", 0.1, 1.0),
+    ("music", 0.1, 1.0),
+    ("sfx", 0.1, 1.0),
+];
+
+// sliders before this one belong to the crt effect
+const VOLUME_START: usize = 5;
+
+/// Options menu for tweaking the CRT effect and volume while playing.
+///
+/// F1 opens and closes it, up and down pick a setting and
+/// left and right change it.
+#[derive(Default)]
+pub struct OptionsMenu {
+    pub open: bool,
+    selected: usize,
+    preset: Option<usize>,
+}
+
+impl OptionsMenu {
+    /// Returns if the settings were changed.
+    pub fn update(&mut self, events: &Events, settings: &mut Settings) -> bool {
+        if events.is_key_typed(Key::F1) {
+            self.open = !self.open;
+        }
+        if !self.open {
+            return false;
+        }
+
+        let count = SLIDERS.len() + 1;
+        if events.is_key_typed(Key::Up) {
+            self.selected = (self.selected + count - 1) % count;
+        }
+        if events.is_key_typed(Key::Down) {
+            self.selected = (self.selected + 1) % count;
+        }
+
+        let change = match (
+            events.is_key_typed(Key::Left),
+            events.is_key_typed(Key::Right),
+        ) {
+            (true, false) => -1,
+            (false, true) => 1,
+            _ => return false,
+        };
+
+        let crt = &mut settings.crt;
+        if self.selected == 0 {
+            // cycle through presets
+            let current = self
+                .preset
+                .or_else(|| {
+                    PRESETS
+                        .iter()
+                        .position(|p| CrtSettings::preset(p) == Some(*crt))
+                })
+                .unwrap_or(0) as i32;
+            let next = (current + change).rem_euclid(PRESETS.len() as i32) as usize;
+            *crt = CrtSettings::preset(PRESETS[next]).expect("bad preset");
+            self.preset = Some(next);
+        } else {
+            let index = self.selected - 1;
+            let (_, step, max) = SLIDERS[index];
+            let value = setting(settings, index);
+            *value = (*value + step * change as f32).max(0.0).min(max);
+            if index < VOLUME_START {
+                settings.crt.enabled = true;
+                self.preset = None;
+            }
+        }
+
+        true
+    }
+
+    pub fn draw(&self, target: &mut Target, settings: &Settings, width: u32, height: u32) {
+        if !self.open {
+            return;
+        }
+
+        let crt = &settings.crt;
+        let preset = self
+            .preset
+            .map(|p| PRESETS[p])
+            .or_else(|| {
+                PRESETS
+                    .iter()
+                    .find(|p| CrtSettings::preset(p) == Some(*crt))
+                    .copied()
+            })
+            .unwrap_or("custom");
+        let mut lines = vec![format!("preset      {}", preset)];
+        let mut copy = *settings;
+        for (i, (name, _, _)) in SLIDERS.iter().enumerate() {
+            lines.push(format!("{:<11} {:.2}", name, setting(&mut copy, i)));
+        }
+
+        target.push();
+        target.shape_mode(ShapeMode::TopLeft);
+        let left = width as f32 / -2.0 + 20.0;
+        let top = height as f32 / 2.0 - 20.0;
+        for (i, line) in lines.iter().enumerate() {
+            let cursor = if i == self.selected { "> " } else { "  " };
+            target.fill(if i == self.selected {
+                "#ffdd00"
+            } else {
+                "#ffffff"
+            });
+            target.text(
+                format!("{}{}", cursor, line),
+                Vec2::new(left, top - i as f32 * 24.0),
+            );
+        }
+        target.pop();
+    }
+}
+
+fn setting(settings: &mut Settings, index: usize) -> &mut f32 {
+    match index {
+        0 => &mut settings.crt.curvature,
+        1 => &mut settings.crt.scanlines,
+        2 => &mut settings.crt.vignette,
+        3 => &mut settings.crt.bloom,
+        4 => &mut settings.crt.aberration,
+        5 => &mut settings.volume.master,
+        6 => &mut settings.volume.music,
+        _ => &mut settings.volume.sfx,
+    }
+}
@@ -0,0 +1,193 @@
+use kira::arrangement::Arrangement;
+use kira::arrangement::ArrangementId;
+use kira::arrangement::ArrangementSettings;
+use kira::arrangement::LoopArrangementSettings;
+use kira::arrangement::SoundClip;
+use kira::instance::InstanceId;
+use kira::instance::InstanceSettings;
+use kira::instance::StopInstanceSettings;
+use kira::manager::AudioManager;
+use kira::mixer::effect::filter::Filter;
+use kira::mixer::effect::filter::FilterSettings;
+use kira::mixer::SubTrackId;
+use kira::mixer::TrackSettings;
+use kira::parameter::ParameterId;
+use kira::Tween;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::error::Error;
+use crate::error::Result;
+
+const CROSSFADE: f64 = 1.5;
+const DUCK_VOLUME: f64 = 0.5;
+const PAUSE_VOLUME: f64 = 0.4;
+const PAUSE_CUTOFF: f64 = 800.0;
+const OPEN_CUTOFF: f64 = 20000.0;
+
+/// Looping background music, one track per level.
+///
+/// Tracks are read from a JSON file. A track is either a `file` that
+/// loops from `loop_start` seconds, or an `intro` file followed by a
+/// looping `file`. Music plays on its own sub-track with a volume and a
+/// low pass filter that duck it under sound effects and muffle it while
+/// the game is paused.
+pub struct Music {
+    tracks: HashMap<String, ArrangementId>,
+    track: SubTrackId,
+    volume: ParameterId,
+    cutoff: ParameterId,
+    current: Option<(String, InstanceId)>,
+    duck_until: Option<Instant>,
+    paused: bool,
+    // last volume and cutoff sent to the mixer
+    levels: (f64, f64),
+}
+
+impl Music {
+    /// Loads tracks that play through the given bus.
+    pub fn load(audio: &mut AudioManager, path: impl AsRef<Path>, bus: SubTrackId) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        let json: Value = serde_json::from_slice(&bytes)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let volume = audio.add_parameter(1.0)?;
+        let cutoff = audio.add_parameter(OPEN_CUTOFF)?;
+        let track = audio.add_sub_track(TrackSettings::new().volume(volume).parent(bus))?;
+        audio.add_effect_to_track(
+            track,
+            Filter::new(FilterSettings::new().cutoff(cutoff)),
+            Default::default(),
+        )?;
+
+        let track_vals = match &json["tracks"] {
+            Value::Object(map) => map,
+            _ => return Err(Error::Audio("tracks are not present".to_string())),
+        };
+
+        let mut tracks = HashMap::new();
+        for (name, track_val) in track_vals {
+            let file = match &track_val["file"] {
+                Value::String(s) => dir.join(s),
+                _ => return Err(Error::Audio(format!("track {} has no file", name))),
+            };
+            let looped = audio.load_sound(file, Default::default())?;
+
+            let arrangement = match (&track_val["intro"], track_val["loop_start"].as_f64()) {
+                (Value::String(intro), _) => {
+                    let intro = audio.load_sound(dir.join(intro), Default::default())?;
+                    Arrangement::new_loop_with_intro(
+                        intro,
+                        looped,
+                        LoopArrangementSettings::new().default_track(track),
+                    )
+                }
+                (_, Some(loop_start)) => {
+                    let mut arrangement = Arrangement::new(
+                        ArrangementSettings::new()
+                            .default_loop_start(loop_start)
+                            .default_track(track),
+                    );
+                    arrangement.add_clip(SoundClip::new(looped, 0.0));
+                    arrangement
+                }
+                _ => Arrangement::new_loop(
+                    looped,
+                    LoopArrangementSettings::new().default_track(track),
+                ),
+            };
+            tracks.insert(name.to_string(), audio.add_arrangement(arrangement)?);
+        }
+
+        Ok(Self {
+            tracks,
+            track,
+            volume,
+            cutoff,
+            current: None,
+            duck_until: None,
+            paused: false,
+            levels: (1.0, OPEN_CUTOFF),
+        })
+    }
+
+    /// Crossfades to a track, or fades out with `None`. Asking for the
+    /// track that is already playing keeps it going.
+    pub fn play(&mut self, audio: &mut AudioManager, name: Option<&str>) -> Result<()> {
+        if self.current.as_ref().map(|c| c.0.as_str()) == name {
+            return Ok(());
+        }
+
+        let next = match name {
+            Some(name) => Some(
+                *self
+                    .tracks
+                    .get(name)
+                    .ok_or_else(|| Error::Audio(format!("unknown music track {}", name)))?,
+            ),
+            None => None,
+        };
+
+        if let Some((_, instance)) = self.current.take() {
+            audio.stop_instance(
+                instance,
+                StopInstanceSettings::new().fade_tween(Tween::from(CROSSFADE)),
+            )?;
+        }
+
+        if let (Some(name), Some(arrangement)) = (name, next) {
+            let instance = audio.play(
+                arrangement,
+                InstanceSettings::new()
+                    .track(self.track)
+                    .fade_in_tween(Tween::from(CROSSFADE)),
+            )?;
+            self.current = Some((name.to_string(), instance));
+        }
+
+        Ok(())
+    }
+
+    /// Lowers the music until a sound effect has finished.
+    pub fn duck(&mut self, until: Instant) {
+        self.duck_until = Some(self.duck_until.map_or(until, |u| u.max(until)));
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Moves the mixer towards the current duck and pause state.
+    pub fn update(&mut self, audio: &mut AudioManager) -> Result<()> {
+        if matches!(self.duck_until, Some(until) if until <= Instant::now()) {
+            self.duck_until = None;
+        }
+
+        let mut volume = 1.0;
+        if self.duck_until.is_some() {
+            volume *= DUCK_VOLUME;
+        }
+        if self.paused {
+            volume *= PAUSE_VOLUME;
+        }
+        let cutoff = if self.paused {
+            PAUSE_CUTOFF
+        } else {
+            OPEN_CUTOFF
+        };
+
+        if (volume, cutoff) != self.levels {
+            // duck quickly, come back slowly
+            let tween = if volume < self.levels.0 { 0.05 } else { 0.4 };
+            audio.set_parameter(self.volume, volume, Tween::from(tween))?;
+            audio.set_parameter(self.cutoff, cutoff, Tween::from(0.3))?;
+            self.levels = (volume, cutoff);
+        }
+
+        Ok(())
+    }
+}
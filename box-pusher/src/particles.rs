@@ -0,0 +1,199 @@
+use duku::Rgb;
+use duku::Vec2;
+use std::f32::consts::PI;
+
+use crate::random::Rng;
+
+const MAX_PARTICLES: usize = 512;
+
+/// How an emitter spawns particles and how they behave. Positions and
+/// speeds are in tiles, sizes in pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Effect {
+    // particles spawned right away
+    pub burst: u32,
+    // particles per second after the burst
+    pub rate: f32,
+    // how long the emitter keeps spawning
+    pub duration: f32,
+    pub lifetime: (f32, f32),
+    pub speed: (f32, f32),
+    // direction range in radians, 0 is right
+    pub angle: (f32, f32),
+    pub gravity: f32,
+    pub drag: f32,
+    // start and end of the size and color curves
+    pub size: (f32, f32),
+    pub colors: [Rgb; 3],
+}
+
+impl Effect {
+    /// Puff kicked up by a push that does not go through.
+    pub fn dust() -> Self {
+        Self {
+            burst: 6,
+            rate: 0.0,
+            duration: 0.0,
+            lifetime: (0.2, 0.4),
+            speed: (0.6, 1.6),
+            angle: (0.15 * PI, 0.85 * PI),
+            gravity: -4.0,
+            drag: 3.0,
+            size: (2.0, 0.0),
+            colors: [
+                Rgb::new(220, 210, 190),
+                Rgb::new(170, 160, 140),
+                Rgb::new(120, 110, 100),
+            ],
+        }
+    }
+
+    /// Glitter around a box that landed on its goal.
+    pub fn sparkle(tint: Rgb) -> Self {
+        Self {
+            burst: 8,
+            rate: 20.0,
+            duration: 0.3,
+            lifetime: (0.3, 0.7),
+            speed: (0.3, 1.5),
+            angle: (0.0, 2.0 * PI),
+            gravity: 0.5,
+            drag: 2.0,
+            size: (2.0, 0.0),
+            colors: [Rgb::new(255, 255, 255), tint, tint],
+        }
+    }
+
+    /// Confetti shot up when the level is done.
+    pub fn confetti() -> Self {
+        Self {
+            burst: 40,
+            rate: 60.0,
+            duration: 0.5,
+            lifetime: (1.0, 1.8),
+            speed: (3.0, 6.0),
+            angle: (0.3 * PI, 0.7 * PI),
+            gravity: -6.0,
+            drag: 1.0,
+            size: (2.0, 1.0),
+            colors: [
+                Rgb::new(255, 240, 110),
+                Rgb::new(255, 110, 160),
+                Rgb::new(110, 200, 255),
+            ],
+        }
+    }
+}
+
+pub struct Particle {
+    pub pos: Vec2,
+    vel: Vec2,
+    age: f32,
+    lifetime: f32,
+    gravity: f32,
+    drag: f32,
+    sizes: (f32, f32),
+    colors: [Rgb; 3],
+}
+
+impl Particle {
+    pub fn size(&self) -> f32 {
+        lerp(self.sizes.0, self.sizes.1, self.life())
+    }
+
+    pub fn color(&self) -> Rgb {
+        // first half goes from start to middle, second half to end
+        let t = self.life() * 2.0;
+        let (from, to, t) = if t < 1.0 {
+            (self.colors[0], self.colors[1], t)
+        } else {
+            (self.colors[1], self.colors[2], t - 1.0)
+        };
+        let mix = |a: u8, b: u8| lerp(a as f32, b as f32, t) as u8;
+        Rgb::new(mix(from.r, to.r), mix(from.g, to.g), mix(from.b, to.b))
+    }
+
+    fn life(&self) -> f32 {
+        (self.age / self.lifetime).min(1.0)
+    }
+}
+
+/// Pool of live particles shared by all emitters. Dead particles are
+/// swapped to the back and reused, so the pool stops allocating once
+/// it has grown.
+pub struct Particles {
+    pool: Vec<Particle>,
+    alive: usize,
+    rng: Rng,
+}
+
+impl Default for Particles {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Particles {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            pool: Vec::with_capacity(MAX_PARTICLES),
+            alive: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub fn emit(&mut self, effect: &Effect, origin: Vec2, count: u32) {
+        for _ in 0..count {
+            // drop particles when the pool is full
+            if self.alive == MAX_PARTICLES {
+                return;
+            }
+
+            let angle = self.rng.between(effect.angle.0, effect.angle.1);
+            let speed = self.rng.between(effect.speed.0, effect.speed.1);
+            let particle = Particle {
+                pos: origin,
+                vel: Vec2::new(angle.cos(), angle.sin()) * speed,
+                age: 0.0,
+                lifetime: self.rng.between(effect.lifetime.0, effect.lifetime.1),
+                gravity: effect.gravity,
+                drag: effect.drag,
+                sizes: effect.size,
+                colors: effect.colors,
+            };
+
+            if self.alive < self.pool.len() {
+                self.pool[self.alive] = particle;
+            } else {
+                self.pool.push(particle);
+            }
+            self.alive += 1;
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        let mut i = 0;
+        while i < self.alive {
+            let p = &mut self.pool[i];
+            p.age += delta_time;
+            if p.age >= p.lifetime {
+                self.alive -= 1;
+                self.pool.swap(i, self.alive);
+                continue;
+            }
+
+            p.vel.y += p.gravity * delta_time;
+            p.vel *= (1.0 - p.drag * delta_time).max(0.0);
+            p.pos += p.vel * delta_time;
+            i += 1;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Particle> {
+        self.pool[..self.alive].iter()
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
@@ -0,0 +1,261 @@
+use duku::glsl::Metadata;
+use duku::Canvas;
+use duku::Duku;
+use duku::Filter;
+use duku::Handle;
+use duku::Material;
+use duku::Shader;
+use duku::Target;
+use duku::Vec4;
+use duku::Wrap;
+use serde_json::Map;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::crt::CrtSettings;
+use crate::error::Error;
+use crate::error::Result;
+use crate::viewport::Viewport;
+
+/// Ordered list of full screen shaders run after the game is drawn.
+///
+/// Every pass reads the previous pass's canvas from `material.a.x` and
+/// the game's resolution from `material.a.yz`. The other material slots
+/// hold the pass's own parameters. Passes run at the viewport's size and
+/// the result is placed inside the window by `present.glsl`.
+pub struct Pipeline {
+    path: PathBuf,
+    meta: Option<Metadata>,
+    passes: Vec<Pass>,
+    present: Present,
+    viewport: Viewport,
+}
+
+struct Pass {
+    name: String,
+    enabled: bool,
+    shader_path: PathBuf,
+    shader: Handle<Shader>,
+    meta: Metadata,
+    material: Handle<Material>,
+    canvas: Handle<Canvas>,
+}
+
+struct Present {
+    shader_path: PathBuf,
+    shader: Handle<Shader>,
+    meta: Metadata,
+    material: Handle<Material>,
+}
+
+impl Pipeline {
+    pub fn load(duku: &mut Duku, path: impl AsRef<Path>, viewport: &Viewport) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let passes = load_passes(duku, &path, canvas_size(viewport))?;
+
+        let shader_path = path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join("present.glsl");
+        let present = Present {
+            shader: duku.create_shader_glsl(&shader_path)?,
+            meta: Metadata::new(&shader_path)?,
+            shader_path,
+            material: duku.create_material(),
+        };
+
+        Ok(Self {
+            meta: Metadata::new(&path).ok(),
+            path,
+            passes,
+            present,
+            viewport: *viewport,
+        })
+    }
+
+    /// Recompiles shaders that changed on disk and reloads the pass list
+    /// if the pipeline file changed. Returns if the pass list was reloaded.
+    pub fn reload(&mut self, duku: &mut Duku) -> Result<bool> {
+        let mut reloaded = false;
+        if self.meta.as_mut().map(|m| m.is_modified()).unwrap_or(false) {
+            self.passes = load_passes(duku, &self.path, canvas_size(&self.viewport))?;
+            reloaded = true;
+        }
+
+        for pass in &mut self.passes {
+            if pass.meta.is_modified() {
+                pass.shader = duku.create_shader_glsl(&pass.shader_path)?;
+            }
+        }
+        if self.present.meta.is_modified() {
+            self.present.shader = duku.create_shader_glsl(&self.present.shader_path)?;
+        }
+
+        Ok(reloaded)
+    }
+
+    /// Resizes the pass canvases to follow a window resize or a new
+    /// scale mode.
+    pub fn resize(&mut self, duku: &mut Duku, viewport: &Viewport) {
+        let (width, height) = canvas_size(viewport);
+        if canvas_size(&self.viewport) != (width, height) {
+            for pass in &self.passes {
+                duku.resize_canvas(&pass.canvas, width, height);
+            }
+        }
+        self.viewport = *viewport;
+    }
+
+    /// Writes CRT settings into the pass named `crt`.
+    pub fn set_crt(&mut self, crt: &CrtSettings) {
+        if let Some(pass) = self.passes.iter_mut().find(|p| p.name == "crt") {
+            pass.enabled = crt.enabled;
+            crt.apply(&mut pass.material.write());
+        }
+    }
+
+    /// Runs all enabled passes over the input canvas, then lets `overlay`
+    /// draw on the screen on top of the result.
+    pub fn draw(&self, duku: &mut Duku, input: &Handle<Canvas>, overlay: impl FnOnce(&mut Target)) {
+        // feed every pass the output of the one before it
+        let mut source = input;
+        for pass in self.passes.iter().filter(|p| p.enabled) {
+            {
+                let mut m = pass.material.write();
+                m.a.x = texture_index(source);
+                m.a.y = self.viewport.view.0 as f32;
+                m.a.z = self.viewport.view.1 as f32;
+            }
+
+            duku.draw_on_canvas(&pass.canvas, None, |t| {
+                t.background("#000000");
+                t.filter(Filter::Nearest);
+                t.wrap(Wrap::ClampBorder);
+                t.material(&pass.material);
+                t.surface(&pass.shader);
+            });
+            source = &pass.canvas;
+        }
+
+        // place the result inside the window
+        {
+            let (offset, size) = self.viewport.rect();
+            let window = (self.viewport.window.0 as f32, self.viewport.window.1 as f32);
+            let mut m = self.present.material.write();
+            m.a.x = texture_index(source);
+            m.b = Vec4::new(
+                offset.x / window.0,
+                offset.y / window.1,
+                size.x / window.0,
+                size.y / window.1,
+            );
+        }
+
+        duku.draw(None, |t| {
+            t.background("#000000");
+            t.filter(Filter::Nearest);
+            t.wrap(Wrap::ClampBorder);
+            t.material(&self.present.material);
+            t.surface(&self.present.shader);
+            overlay(t);
+        });
+    }
+}
+
+fn texture_index(canvas: &Handle<Canvas>) -> f32 {
+    canvas.read().shader_index(0).expect("no texture") as f32
+}
+
+fn canvas_size(viewport: &Viewport) -> (u32, u32) {
+    let (_, size) = viewport.rect();
+    (
+        (size.x.round() as u32).max(1),
+        (size.y.round() as u32).max(1),
+    )
+}
+
+fn load_passes(duku: &mut Duku, path: &Path, size: (u32, u32)) -> Result<Vec<Pass>> {
+    let bytes = fs::read(path)?;
+    let json: Value = serde_json::from_slice(&bytes)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let pass_vals = match &json["passes"] {
+        Value::Array(vec) => vec,
+        _ => return Err(invalid("passes are not present")),
+    };
+
+    let mut passes = vec![];
+    for pass_val in pass_vals {
+        let pass = match pass_val {
+            Value::Object(map) => map,
+            _ => return Err(invalid("invalid pass")),
+        };
+        let shader_path = match &pass["shader"] {
+            Value::String(s) => dir.join(s),
+            _ => return Err(invalid("pass has no shader")),
+        };
+        let name = match pass.get("name") {
+            Some(Value::String(s)) => s.to_string(),
+            _ => shader_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("")
+                .to_string(),
+        };
+        let enabled = !matches!(pass.get("enabled"), Some(Value::Bool(false)));
+
+        let material = duku.create_material();
+        set_params(&mut material.write(), pass)?;
+
+        passes.push(Pass {
+            name,
+            enabled,
+            shader: duku.create_shader_glsl(&shader_path)?,
+            meta: Metadata::new(&shader_path)?,
+            shader_path,
+            material,
+            canvas: duku.create_canvas(size.0, size.1),
+        });
+    }
+
+    Ok(passes)
+}
+
+fn set_params(material: &mut Material, pass: &Map<String, Value>) -> Result<()> {
+    let params = match pass.get("params") {
+        Some(Value::Object(map)) => map,
+        Some(_) => return Err(invalid("invalid pass params")),
+        None => return Ok(()),
+    };
+
+    for (slot, value) in params {
+        let v = match value {
+            Value::Array(vec) => {
+                let n = |i: usize| vec.get(i).and_then(|v| v.as_f64()).unwrap_or(0.0) as f32;
+                Vec4::new(n(0), n(1), n(2), n(3))
+            }
+            Value::Number(n) => Vec4::new(n.as_f64().unwrap_or(0.0) as f32, 0.0, 0.0, 0.0),
+            _ => return Err(invalid("invalid pass param")),
+        };
+
+        // slot a is taken by the input
+        match slot.as_str() {
+            "b" => material.b = v,
+            "c" => material.c = v,
+            "d" => material.d = v,
+            "e" => material.e = v,
+            "f" => material.f = v,
+            "g" => material.g = v,
+            "h" => material.h = v,
+            _ => return Err(invalid("invalid pass param slot")),
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidJson(message.to_string())
+}
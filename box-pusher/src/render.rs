@@ -0,0 +1,524 @@
+use duku::Duku;
+use duku::Filter;
+use duku::Handle;
+use duku::Rgb;
+use duku::ShapeMode;
+use duku::Target;
+use duku::Texture;
+use duku::Vec2;
+use png::BitDepth;
+use png::ColorType;
+use png::Decoder;
+use png::Encoder;
+use png::Transformations;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::error::Result;
+
+/// Sprite sheets entities can be drawn with.
+pub const SPRITES: [&str; 10] = [
+    "player.png",
+    "floor.png",
+    "wall.png",
+    "box.png",
+    "plate.png",
+    "door.png",
+    "teleporter.png",
+    "goal.png",
+    "oneway.png",
+    "conveyor.png",
+];
+
+/// Draw calls the draw system makes.
+///
+/// Coordinates follow duku: the origin starts in the middle, y points up
+/// and shapes hang down and to the right from their position.
+pub trait Renderer {
+    fn push(&mut self);
+    fn pop(&mut self);
+    fn translate_x(&mut self, x: f32);
+    fn translate_y(&mut self, y: f32);
+
+    /// Smaller depth is drawn in front.
+    fn translate_z(&mut self, z: f32);
+
+    fn tint(&mut self, color: Rgb);
+    fn fill(&mut self, color: Rgb);
+    fn no_fill(&mut self);
+    fn stroke(&mut self, color: Rgb);
+    fn no_stroke(&mut self);
+    fn stroke_weight(&mut self, weight: f32);
+
+    fn texture_part(
+        &mut self,
+        texture: &str,
+        pos: Vec2,
+        size: Vec2,
+        part_pos: Vec2,
+        part_size: Vec2,
+    );
+
+    fn rect(&mut self, pos: Vec2, size: Vec2);
+    fn line(&mut self, from: Vec2, to: Vec2);
+}
+
+/// Loads every sprite sheet from `dir` onto the GPU.
+pub fn load_textures(
+    duku: &mut Duku,
+    dir: impl AsRef<Path>,
+) -> Result<HashMap<String, Handle<Texture>>> {
+    let mut textures = HashMap::new();
+    for name in SPRITES.iter() {
+        let texture = duku.create_texture_png(dir.as_ref().join(name), None)?;
+        textures.insert(name.to_string(), texture);
+    }
+    Ok(textures)
+}
+
+/// Draws through duku onto a window or canvas.
+pub struct GpuRenderer<'t> {
+    target: &'t mut Target,
+    textures: &'t HashMap<String, Handle<Texture>>,
+}
+
+impl<'t> GpuRenderer<'t> {
+    pub fn new(target: &'t mut Target, textures: &'t HashMap<String, Handle<Texture>>) -> Self {
+        target.filter(Filter::Nearest);
+        target.shape_mode(ShapeMode::TopLeft);
+        Self { target, textures }
+    }
+}
+
+impl Renderer for GpuRenderer<'_> {
+    fn push(&mut self) {
+        self.target.push();
+    }
+
+    fn pop(&mut self) {
+        self.target.pop();
+    }
+
+    fn translate_x(&mut self, x: f32) {
+        self.target.translate_x(x);
+    }
+
+    fn translate_y(&mut self, y: f32) {
+        self.target.translate_y(y);
+    }
+
+    fn translate_z(&mut self, z: f32) {
+        self.target.translate_z(z);
+    }
+
+    fn tint(&mut self, color: Rgb) {
+        self.target.tint(color);
+    }
+
+    fn fill(&mut self, color: Rgb) {
+        self.target.fill(color);
+    }
+
+    fn no_fill(&mut self) {
+        self.target.no_fill();
+    }
+
+    fn stroke(&mut self, color: Rgb) {
+        self.target.stroke(color);
+    }
+
+    fn no_stroke(&mut self) {
+        self.target.no_stroke();
+    }
+
+    fn stroke_weight(&mut self, weight: f32) {
+        self.target.stroke_weight(weight);
+    }
+
+    fn texture_part(
+        &mut self,
+        texture: &str,
+        pos: Vec2,
+        size: Vec2,
+        part_pos: Vec2,
+        part_size: Vec2,
+    ) {
+        let texture = self.textures.get(texture).expect("bad sprite");
+        self.target
+            .texture_part(texture, pos, size, part_pos, part_size);
+    }
+
+    fn rect(&mut self, pos: Vec2, size: Vec2) {
+        self.target.rect(pos, size);
+    }
+
+    fn line(&mut self, from: Vec2, to: Vec2) {
+        self.target.line(from, to);
+    }
+}
+
+/// 8 bit RGBA pixels, rows from the top.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    pub fn load_png(path: impl AsRef<Path>) -> Result<Self> {
+        let mut decoder = Decoder::new(File::open(path)?);
+        decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf)?;
+
+        // expanding leaves gray or rgb pixels that still need alpha
+        let (color, _) = reader.output_color_type();
+        let data = match color {
+            ColorType::RGBA => buf,
+            ColorType::RGB => buf
+                .chunks(3)
+                .flat_map(|p| vec![p[0], p[1], p[2], 255])
+                .collect(),
+            ColorType::GrayscaleAlpha => buf
+                .chunks(2)
+                .flat_map(|p| vec![p[0], p[0], p[0], p[1]])
+                .collect(),
+            ColorType::Grayscale => buf.iter().flat_map(|&g| vec![g, g, g, 255]).collect(),
+            ColorType::Indexed => return Err(Error::Image("unexpanded png palette".to_string())),
+        };
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            data,
+        })
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = Encoder::new(file, self.width, self.height);
+        encoder.set_color(ColorType::RGBA);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.data)?;
+        Ok(())
+    }
+
+    /// Nearest whole number scale up, keeping pixels sharp.
+    pub fn scaled(&self, scale: u32) -> Self {
+        let scale = scale.max(1);
+        let mut image = Self::new(self.width * scale, self.height * scale);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                image.set_pixel(x, y, self.pixel(x / scale, y / scale));
+            }
+        }
+        image
+    }
+
+    /// Number of pixels that differ by more than `tolerance` in any channel.
+    pub fn diff(&self, other: &Self, tolerance: u8) -> Option<usize> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        let count = self
+            .data
+            .chunks(4)
+            .zip(other.data.chunks(4))
+            .filter(|(a, b)| {
+                a.iter()
+                    .zip(b.iter())
+                    .any(|(a, b)| (*a as i32 - *b as i32).abs() > tolerance as i32)
+            })
+            .count();
+        Some(count)
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        [
+            self.data[i],
+            self.data[i + 1],
+            self.data[i + 2],
+            self.data[i + 3],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        let i = ((y * self.width + x) * 4) as usize;
+        self.data[i..i + 4].copy_from_slice(&pixel);
+    }
+
+    /// Draws a color over the pixel, mixing by its alpha.
+    fn blend(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let under = self.pixel(x, y);
+        let a = color[3] as f32 / 255.0;
+        let mix = |c: u8, u: u8| (c as f32 * a + u as f32 * (1.0 - a)).round() as u8;
+        let alpha = (color[3] as f32 + under[3] as f32 * (1.0 - a)).round() as u8;
+        self.set_pixel(
+            x,
+            y,
+            [
+                mix(color[0], under[0]),
+                mix(color[1], under[1]),
+                mix(color[2], under[2]),
+                alpha,
+            ],
+        );
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct State {
+    offset: Vec2,
+    depth: f32,
+    tint: Rgb,
+    fill: Option<Rgb>,
+    stroke: Option<Rgb>,
+    stroke_weight: f32,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::default(),
+            depth: 0.0,
+            tint: Rgb::new(255, 255, 255),
+            fill: Some(Rgb::new(255, 255, 255)),
+            stroke: None,
+            stroke_weight: 1.0,
+        }
+    }
+}
+
+/// Draws on the CPU into an image, for machines without a GPU.
+///
+/// Only opaque pixels write depth, so see through parts of a sprite
+/// never hide what is drawn behind them later.
+pub struct SoftwareRenderer {
+    image: Image,
+    depth: Vec<f32>,
+    textures: HashMap<String, Image>,
+    state: State,
+    stack: Vec<State>,
+}
+
+impl SoftwareRenderer {
+    /// Loads every sprite sheet from `dir`.
+    pub fn new(width: u32, height: u32, dir: impl AsRef<Path>) -> Result<Self> {
+        let mut textures = HashMap::new();
+        for name in SPRITES.iter() {
+            textures.insert(name.to_string(), Image::load_png(dir.as_ref().join(name))?);
+        }
+
+        Ok(Self {
+            image: Image::new(width, height),
+            depth: vec![f32::INFINITY; (width * height) as usize],
+            textures,
+            state: State::default(),
+            stack: vec![],
+        })
+    }
+
+    /// Clears the image and drawing state for the next frame.
+    pub fn clear(&mut self) {
+        self.image = Image::new(self.image.width, self.image.height);
+        self.depth.iter_mut().for_each(|d| *d = f32::INFINITY);
+        self.state = State::default();
+        self.stack.clear();
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Pixel bounds covered by a rectangle, clipped to the image.
+    fn bounds(&self, pos: Vec2, size: Vec2) -> (u32, u32, u32, u32) {
+        let (left, top) = self.to_pixel(pos);
+        let clip = |v: f32, max: u32| v.round().max(0.0).min(max as f32) as u32;
+        (
+            clip(left, self.image.width),
+            clip(top, self.image.height),
+            clip(left + size.x, self.image.width),
+            clip(top + size.y, self.image.height),
+        )
+    }
+
+    fn to_pixel(&self, pos: Vec2) -> (f32, f32) {
+        let pos = pos + self.state.offset;
+        (
+            self.image.width as f32 / 2.0 + pos.x,
+            self.image.height as f32 / 2.0 - pos.y,
+        )
+    }
+
+    fn plot(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let i = (y * self.image.width + x) as usize;
+        if color[3] == 0 || self.state.depth > self.depth[i] {
+            return;
+        }
+        if color[3] == 255 {
+            self.depth[i] = self.state.depth;
+        }
+        self.image.blend(x, y, color);
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn push(&mut self) {
+        self.stack.push(self.state);
+    }
+
+    fn pop(&mut self) {
+        if let Some(state) = self.stack.pop() {
+            self.state = state;
+        }
+    }
+
+    fn translate_x(&mut self, x: f32) {
+        self.state.offset.x += x;
+    }
+
+    fn translate_y(&mut self, y: f32) {
+        self.state.offset.y += y;
+    }
+
+    fn translate_z(&mut self, z: f32) {
+        self.state.depth += z;
+    }
+
+    fn tint(&mut self, color: Rgb) {
+        self.state.tint = color;
+    }
+
+    fn fill(&mut self, color: Rgb) {
+        self.state.fill = Some(color);
+    }
+
+    fn no_fill(&mut self) {
+        self.state.fill = None;
+    }
+
+    fn stroke(&mut self, color: Rgb) {
+        self.state.stroke = Some(color);
+    }
+
+    fn no_stroke(&mut self) {
+        self.state.stroke = None;
+    }
+
+    fn stroke_weight(&mut self, weight: f32) {
+        self.state.stroke_weight = weight;
+    }
+
+    fn texture_part(
+        &mut self,
+        texture: &str,
+        pos: Vec2,
+        size: Vec2,
+        part_pos: Vec2,
+        part_size: Vec2,
+    ) {
+        let (left, top) = self.to_pixel(pos);
+        let (x0, y0, x1, y1) = self.bounds(pos, size);
+        let tint = self.state.tint;
+        let mut plotted = vec![];
+        {
+            let texture = self.textures.get(texture).expect("bad sprite");
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    // sample the texel under the pixel's center
+                    let u = part_pos.x + (x as f32 + 0.5 - left) / size.x * part_size.x;
+                    let v = part_pos.y + (y as f32 + 0.5 - top) / size.y * part_size.y;
+                    if u < 0.0 || v < 0.0 || u >= texture.width as f32 || v >= texture.height as f32
+                    {
+                        continue;
+                    }
+                    let [r, g, b, a] = texture.pixel(u as u32, v as u32);
+                    let mul = |c: u8, t: u8| (c as u32 * t as u32 / 255) as u8;
+                    plotted.push((x, y, [mul(r, tint.r), mul(g, tint.g), mul(b, tint.b), a]));
+                }
+            }
+        }
+
+        for (x, y, color) in plotted {
+            self.plot(x, y, color);
+        }
+    }
+
+    fn rect(&mut self, pos: Vec2, size: Vec2) {
+        if let Some(fill) = self.state.fill {
+            let (x0, y0, x1, y1) = self.bounds(pos, size);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    self.plot(x, y, [fill.r, fill.g, fill.b, 255]);
+                }
+            }
+        }
+
+        if self.state.stroke.is_some() {
+            let corners = [
+                pos,
+                pos + Vec2::new(size.x, 0.0),
+                pos + Vec2::new(size.x, -size.y),
+                pos + Vec2::new(0.0, -size.y),
+            ];
+            for i in 0..4 {
+                self.line(corners[i], corners[(i + 1) % 4]);
+            }
+        }
+    }
+
+    fn line(&mut self, from: Vec2, to: Vec2) {
+        let stroke = match self.state.stroke {
+            Some(stroke) => stroke,
+            None => return,
+        };
+
+        // thin lines still cover a whole pixel
+        let (x0, y0) = self.to_pixel(from);
+        let (x1, y1) = self.to_pixel(to);
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as u32;
+        let alpha = (self.state.stroke_weight.min(1.0) * 255.0) as u8;
+        for i in 0..=steps {
+            let t = i as f32 / steps as f32;
+            let x = (x0 + (x1 - x0) * t).floor();
+            let y = (y0 + (y1 - y0) * t).floor();
+            // points off the image are clipped, not pulled onto the edge
+            let (width, height) = self.image.size();
+            if x >= 0.0 && y >= 0.0 && x < width as f32 && y < height as f32 {
+                self.plot(x as u32, y as u32, [stroke.r, stroke.g, stroke.b, alpha]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_off_the_image_are_clipped() {
+        let mut renderer = SoftwareRenderer::new(8, 8, "assets").unwrap();
+        renderer.stroke(Rgb::new(255, 255, 255));
+        renderer.line(Vec2::new(-2.0, -10.0), Vec2::new(2.0, -10.0));
+        renderer.line(Vec2::new(10.0, -2.0), Vec2::new(10.0, 2.0));
+        assert_eq!(renderer.image(), &Image::new(8, 8));
+    }
+}
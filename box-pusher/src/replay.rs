@@ -0,0 +1,294 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::resources::Button;
+
+const MAGIC: &[u8; 4] = b"BPRP";
+const FORMAT: u8 = 2;
+
+// bit of each button in a tick, the top bit is for the pause
+const BUTTONS: [Button; 12] = [
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+    Button::Action,
+    Button::Undo,
+    Button::Restart,
+    Button::Save,
+    Button::Slot1,
+    Button::Slot2,
+    Button::Slot3,
+    Button::Hint,
+];
+const PAUSED: u16 = 1 << 15;
+
+/// Buttons held during one frame, and if gameplay was paused by the menu
+/// or editor. Gameplay steps once per frame, so frames are all a replay
+/// needs to reproduce a session.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Tick(u16);
+
+impl Tick {
+    pub fn new(buttons: &HashSet<Button>, paused: bool) -> Self {
+        let mut bits = if paused { PAUSED } else { 0 };
+        for (i, button) in BUTTONS.iter().enumerate() {
+            if buttons.contains(button) {
+                bits |= 1 << i;
+            }
+        }
+        Self(bits)
+    }
+
+    pub fn buttons(self) -> HashSet<Button> {
+        BUTTONS
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.0 & (1 << i) != 0)
+            .map(|(_, b)| *b)
+            .collect()
+    }
+
+    pub fn paused(self) -> bool {
+        self.0 & PAUSED != 0
+    }
+}
+
+/// Everything needed to play a session back.
+///
+/// Files start with `BPRP`, a format byte, the game version, level file
+/// and level name as length prefixed strings and the seed. Then come runs
+/// of identical ticks, each a LEB128 count followed by the tick's two
+/// bytes, little endian.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub version: String,
+    pub level_file: PathBuf,
+    pub level: String,
+    pub seed: u64,
+    // runs of identical ticks as they were stored, so a small file can't
+    // ask for more memory than it takes up
+    pub runs: Vec<(u32, Tick)>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut reader = Reader { bytes: &bytes };
+
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not a replay file"));
+        }
+        let format = reader.take(1)?[0];
+        if format != FORMAT {
+            return Err(invalid(&format!("unknown replay format {}", format)));
+        }
+
+        let version = reader.string()?;
+        let level_file = PathBuf::from(reader.string()?);
+        let level = reader.string()?;
+        let mut seed = [0; 8];
+        seed.copy_from_slice(reader.take(8)?);
+
+        let mut runs = vec![];
+        while !reader.bytes.is_empty() {
+            let count = reader.varint()?;
+            let bits = reader.take(2)?;
+            let tick = Tick(u16::from_le_bytes([bits[0], bits[1]]));
+            runs.push((count, tick));
+        }
+
+        Ok(Self {
+            version,
+            level_file,
+            level,
+            seed: u64::from_le_bytes(seed),
+            runs,
+        })
+    }
+
+    pub fn ticks(&self) -> impl Iterator<Item = Tick> + '_ {
+        self.runs
+            .iter()
+            .flat_map(|&(count, tick)| (0..count).map(move |_| tick))
+    }
+
+    pub fn into_ticks(self) -> impl Iterator<Item = Tick> {
+        self.runs
+            .into_iter()
+            .flat_map(|(count, tick)| (0..count).map(move |_| tick))
+    }
+}
+
+/// Writes ticks to a replay file as they happen. The last run of ticks
+/// is written when the writer is finished or dropped.
+pub struct ReplayWriter {
+    file: BufWriter<File>,
+    run: Option<(Tick, u32)>,
+}
+
+impl ReplayWriter {
+    pub fn create(
+        path: impl AsRef<Path>,
+        level_file: &Path,
+        level: &str,
+        seed: u64,
+    ) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&[FORMAT])?;
+        write_string(&mut file, env!("CARGO_PKG_VERSION"))?;
+        write_string(&mut file, &level_file.to_string_lossy())?;
+        write_string(&mut file, level)?;
+        file.write_all(&seed.to_le_bytes())?;
+
+        Ok(Self { file, run: None })
+    }
+
+    pub fn push(&mut self, tick: Tick) -> Result<()> {
+        match &mut self.run {
+            Some((last, count)) if *last == tick && *count < u32::MAX => *count += 1,
+            _ => {
+                self.write_run()?;
+                self.run = Some((tick, 1));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.write_run()?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn write_run(&mut self) -> Result<()> {
+        if let Some((tick, count)) = self.run.take() {
+            let mut count = count;
+            // 7 bits at a time, the top bit marks that more follow
+            loop {
+                let byte = (count & 0x7f) as u8;
+                count >>= 7;
+                if count == 0 {
+                    self.file.write_all(&[byte])?;
+                    break;
+                }
+                self.file.write_all(&[byte | 0x80])?;
+            }
+            self.file.write_all(&tick.0.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ReplayWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.write_run() {
+            println!("{}", err);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < count {
+            return Err(invalid("replay file ends early"));
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.take(2)?;
+        let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("invalid replay text"))
+    }
+
+    fn varint(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("invalid replay tick count"))
+    }
+}
+
+fn write_string(file: &mut impl Write, text: &str) -> Result<()> {
+    let len = text.len().min(u16::MAX as usize);
+    file.write_all(&(len as u16).to_le_bytes())?;
+    file.write_all(&text.as_bytes()[..len])?;
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidReplay(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn tick_bits() {
+        for button in BUTTONS.iter() {
+            let buttons: HashSet<_> = vec![*button].into_iter().collect();
+            let tick = Tick::new(&buttons, false);
+            assert_eq!(tick.buttons(), buttons);
+            assert!(!tick.paused());
+        }
+
+        let all: HashSet<_> = BUTTONS.iter().copied().collect();
+        let tick = Tick::new(&all, true);
+        assert_eq!(tick.buttons(), all);
+        assert!(tick.paused());
+        assert_eq!(Tick::new(&HashSet::new(), true).0, PAUSED);
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = env::temp_dir().join("box-pusher-round-trip.replay");
+        let up: HashSet<_> = vec![Button::Up].into_iter().collect();
+        let save: HashSet<_> = vec![Button::Save, Button::Slot3].into_iter().collect();
+        // counts that take one, two and three bytes
+        let runs = vec![
+            (1, Tick::new(&up, false)),
+            (300, Tick::default()),
+            (20_000, Tick::new(&save, true)),
+            (127, Tick::new(&up, true)),
+        ];
+
+        let mut writer =
+            ReplayWriter::create(&path, Path::new("levels.ldtk"), "First", 42).unwrap();
+        for (count, tick) in &runs {
+            for _ in 0..*count {
+                writer.push(*tick).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replay.level_file, PathBuf::from("levels.ldtk"));
+        assert_eq!(replay.level, "First");
+        assert_eq!(replay.seed, 42);
+        assert_eq!(replay.runs, runs);
+        assert_eq!(replay.ticks().count(), 20_428);
+    }
+}
@@ -1,14 +1,307 @@
+use duku::Vec2;
+use specs::storage::MaskedStorage;
+use specs::world::EntitiesRes;
+use specs::Entity;
+use specs::Join;
+use specs::Storage;
+use std::cmp::Reverse;
 use std::collections::HashSet;
+use std::ops::Deref;
+use std::ops::DerefMut;
+
+use crate::components::Color;
+use crate::components::Direction;
+use crate::components::Movable;
+use crate::components::Player;
+use crate::components::Position;
+use crate::components::PressurePlate;
+use crate::components::Sprite;
+use crate::components::Warp;
 
 #[derive(Default)]
 pub struct Inputs {
     pub keys_pressed: HashSet<Button>,
+    pub keys_typed: HashSet<Button>,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Button {
     Up,
     Down,
     Left,
     Right,
+    Action,
+    Undo,
+    Hint,
+    Restart,
+    // held to save a checkpoint instead of loading it
+    Save,
+    Slot1,
+    Slot2,
+    Slot3,
+}
+
+/// Buttons for each checkpoint slot.
+pub const SLOTS: [Button; 3] = [Button::Slot1, Button::Slot2, Button::Slot3];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rules {
+    pub pull: bool,
+    pub chain: u32,
+    pub strength: Option<u32>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            pull: false,
+            chain: 1,
+            strength: None,
+        }
+    }
+}
+
+/// Solver hint shown on the board until the player moves: a ghost of
+/// the walk to the next push, and the box to push.
+#[derive(Default)]
+pub struct HintMarks {
+    pub path: Vec<Tile>,
+    pub push: Option<(Tile, Direction)>,
+}
+
+#[derive(Default)]
+pub struct Progress {
+    pub complete: bool,
+    // boxes resting on a goal of their color
+    pub placed: HashSet<Entity>,
+    // placed has been filled in for the board, cleared when it is restored
+    pub checked: bool,
+}
+
+/// Boards to go back to: before each turn for undo, the level as it was
+/// spawned for restarting, and the player's checkpoints.
+#[derive(Default)]
+pub struct History {
+    pub turns: Vec<Snapshot>,
+    pub start: Option<Snapshot>,
+    pub checkpoints: [Option<Snapshot>; SLOTS.len()],
+}
+
+#[derive(Clone)]
+pub struct Snapshot {
+    pub positions: Vec<(Entity, i32, i32, Direction)>,
+    pub plates: Vec<(Entity, bool, bool)>,
+}
+
+impl Snapshot {
+    /// Remembers where the player and boxes are, and the plate states.
+    pub fn take<P, L, M, T>(
+        positions: &Storage<Position, P>,
+        players: &Storage<Player, L>,
+        movables: &Storage<Movable, M>,
+        plates: &Storage<PressurePlate, T>,
+        entities: &EntitiesRes,
+    ) -> Self
+    where
+        P: Deref<Target = MaskedStorage<Position>>,
+        L: Deref<Target = MaskedStorage<Player>>,
+        M: Deref<Target = MaskedStorage<Movable>>,
+        T: Deref<Target = MaskedStorage<PressurePlate>>,
+    {
+        Self {
+            positions: (positions, entities)
+                .join()
+                .filter(|(_, e)| players.contains(*e) || movables.contains(*e))
+                .map(|(pos, e)| (e, pos.x, pos.y, pos.direction))
+                .collect(),
+            plates: (plates, entities)
+                .join()
+                .map(|(p, e)| (e, p.pressed, p.active))
+                .collect(),
+        }
+    }
+
+    /// Puts everything back, without sliding or warping.
+    pub fn restore<P, T, S, W>(
+        &self,
+        positions: &mut Storage<Position, P>,
+        plates: &mut Storage<PressurePlate, T>,
+        sprites: &mut Storage<Sprite, S>,
+        warps: &mut Storage<Warp, W>,
+    ) where
+        P: DerefMut<Target = MaskedStorage<Position>>,
+        T: DerefMut<Target = MaskedStorage<PressurePlate>>,
+        S: DerefMut<Target = MaskedStorage<Sprite>>,
+        W: DerefMut<Target = MaskedStorage<Warp>>,
+    {
+        for (entity, x, y, direction) in &self.positions {
+            if let Some(pos) = positions.get_mut(*entity) {
+                pos.x = *x;
+                pos.y = *y;
+                pos.direction = *direction;
+                pos.offset = Vec2::default();
+            }
+            warps.remove(*entity);
+        }
+        for (entity, pressed, active) in &self.plates {
+            if let Some(plate) = plates.get_mut(*entity) {
+                plate.pressed = *pressed;
+                plate.active = *active;
+                if let Some(spr) = sprites.get_mut(*entity) {
+                    spr.part_pos = if plate.pressed {
+                        plate.down_part
+                    } else {
+                        plate.up_part
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Sorted sprite order from the last frame. The keys are gathered every
+/// frame, since sliding sprites change them, but they are only sorted
+/// again when they differ from last frame's.
+#[derive(Default)]
+pub struct DrawOrder {
+    pub keys: Vec<(Entity, DrawKey)>,
+    pub entities: Vec<Entity>,
+}
+
+/// Bottom layers first, flat sprites before standing ones, then standing
+/// ones from back to front.
+pub type DrawKey = (Reverse<u32>, bool, Reverse<i32>);
+
+type Tile = (i32, i32);
+
+/// Things that happened in gameplay this frame. Gameplay systems write
+/// them, any number of systems read them and the queue is cleared at the
+/// end of the frame.
+#[derive(Default)]
+pub struct GameEvents {
+    pub events: Vec<GameEvent>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GameEvent {
+    PlayerMoved {
+        from: Tile,
+        to: Tile,
+        direction: Direction,
+    },
+    BoxPushed {
+        entity: Entity,
+        from: Tile,
+        to: Tile,
+        direction: Direction,
+    },
+    // the player walked into something that did not move, tile is in front
+    PushBlocked {
+        tile: Tile,
+        direction: Direction,
+        against_box: bool,
+    },
+    BoxOnGoal {
+        entity: Entity,
+        tile: Tile,
+        color: Option<Color>,
+    },
+    BoxOffGoal {
+        entity: Entity,
+        tile: Tile,
+    },
+    LevelComplete,
+    Undo,
+    Restart,
+    CheckpointSaved {
+        slot: usize,
+    },
+    CheckpointLoaded {
+        slot: usize,
+    },
+}
+
+impl GameEvent {
+    /// Name used to refer to the event in data files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PlayerMoved { .. } => "PlayerMoved",
+            Self::BoxPushed { .. } => "BoxPushed",
+            Self::PushBlocked { .. } => "PushBlocked",
+            Self::BoxOnGoal { .. } => "BoxOnGoal",
+            Self::BoxOffGoal { .. } => "BoxOffGoal",
+            Self::LevelComplete => "LevelComplete",
+            Self::Undo => "Undo",
+            Self::Restart => "Restart",
+            Self::CheckpointSaved { .. } => "CheckpointSaved",
+            Self::CheckpointLoaded { .. } => "CheckpointLoaded",
+        }
+    }
+}
+
+impl GameEvents {
+    pub fn send(&mut self, event: GameEvent) {
+        self.events.push(event);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &GameEvent> {
+        self.events.iter()
+    }
+}
+
+/// Short messages for the player, shown on screen until they get old.
+#[derive(Default)]
+pub struct Messages {
+    pub messages: Vec<Message>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub text: String,
+    // seconds on screen so far
+    pub age: f32,
+}
+
+impl Messages {
+    pub fn show(&mut self, text: impl Into<String>) {
+        self.messages.push(Message {
+            text: text.into(),
+            age: 0.0,
+        });
+    }
+}
+
+/// Counters for the current level.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub moves: u32,
+    pub pushes: u32,
+    pub blocked: u32,
+    pub undos: u32,
+}
+
+/// Achievements unlocked this session. They outlive level changes.
+#[derive(Default)]
+pub struct Achievements {
+    pub unlocked: HashSet<Achievement>,
+    pub boxes_placed: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Achievement {
+    FirstSolve,
+    NoUndo,
+    NoBumps,
+    HundredBoxes,
+}
+
+impl Achievement {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::FirstSolve => "First solve",
+            Self::NoUndo => "No second thoughts",
+            Self::NoBumps => "Light touch",
+            Self::HundredBoxes => "Warehouse veteran",
+        }
+    }
 }
@@ -0,0 +1,40 @@
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+use crate::audio::Volume;
+use crate::crt::CrtSettings;
+use crate::error::Result;
+
+/// Player settings kept between runs.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub crt: CrtSettings,
+    pub volume: Volume,
+}
+
+impl Settings {
+    /// Loads settings from a JSON file. Missing sections use defaults.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let json: Value = serde_json::from_slice(&bytes)?;
+
+        Ok(Self {
+            crt: match &json["crt"] {
+                Value::Null => CrtSettings::default(),
+                crt => CrtSettings::from_json(crt)?,
+            },
+            volume: Volume::from_json(&json["volume"]),
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let json = json!({
+            "crt": self.crt.to_json(),
+            "volume": self.volume.to_json(),
+        });
+        fs::write(path, serde_json::to_string_pretty(&json)?)?;
+        Ok(())
+    }
+}
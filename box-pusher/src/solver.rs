@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::components::Color;
+use crate::components::Direction;
+use crate::error::Result;
+use crate::level::InstanceKind;
+use crate::level::Level;
+use crate::validate;
+
+pub type Tile = (i32, i32);
+
+/// Classic box pushing board: only walls, boxes, goals and the player.
+#[derive(Clone)]
+pub struct Puzzle {
+    pub floor: HashSet<Tile>,
+    pub goals: HashMap<Tile, Option<Color>>,
+    pub boxes: Vec<(Tile, Option<Color>)>,
+    pub player: Tile,
+}
+
+/// Where the boxes and player are, the part of a puzzle that changes
+/// while playing. Boxes are sorted so equal boards compare equal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Board {
+    pub boxes: Vec<(Tile, Option<Color>)>,
+    pub player: Tile,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub direction: Direction,
+    pub push: bool,
+}
+
+pub struct Solution {
+    pub moves: Vec<Move>,
+    pub states: usize,
+}
+
+pub enum Outcome {
+    Solved(Solution),
+    Unsolvable,
+    GaveUp,
+}
+
+struct Node {
+    boxes: Vec<(Tile, Option<Color>)>,
+    player: Tile,
+    parent: usize,
+    push: Option<(Tile, Direction)>,
+}
+
+impl Puzzle {
+    pub fn from_level(level: &Level) -> Result<Self> {
+        let floor = validate::floor(level);
+        let mut goals = HashMap::new();
+        let mut boxes = vec![];
+        let mut player = None;
+        for instance in &level.instances {
+            let tile = (instance.x, instance.y);
+            match &instance.kind {
+                InstanceKind::Player if player.is_none() => player = Some(tile),
+                InstanceKind::Player => return Err("more than one player".into()),
+                InstanceKind::Box(color) => boxes.push((tile, *color)),
+                InstanceKind::Goal(color) => {
+                    goals.insert(tile, *color);
+                }
+                _ => return Err("solver only supports boxes and goals".into()),
+            }
+        }
+
+        let rules = level.rules;
+        if rules.pull || rules.chain > 1 {
+            return Err("solver only supports classic rules".into());
+        }
+
+        Ok(Self {
+            floor,
+            goals,
+            boxes,
+            player: player.ok_or("no player")?,
+        })
+    }
+
+    /// Same walls and goals with the boxes and player from a board.
+    pub fn with_board(&self, board: &Board) -> Self {
+        Self {
+            boxes: board.boxes.clone(),
+            player: board.player,
+            ..self.clone()
+        }
+    }
+
+    pub fn is_solved(&self) -> bool {
+        is_solved(&self.goals, &self.boxes)
+    }
+
+    /// Tiles from which a box of each color can still be pushed onto a goal
+    /// of that color, ignoring other boxes.
+    pub fn live_squares(&self) -> HashMap<Option<Color>, HashSet<Tile>> {
+        let colors: HashSet<_> = self.boxes.iter().map(|b| b.1).collect();
+        colors
+            .into_iter()
+            .map(|color| {
+                let mut live: HashSet<_> = self
+                    .goals
+                    .iter()
+                    .filter(|(_, c)| **c == color)
+                    .map(|(t, _)| *t)
+                    .collect();
+
+                // pull boxes backwards away from the goals
+                let mut queue: VecDeque<_> = live.iter().copied().collect();
+                while let Some((x, y)) = queue.pop_front() {
+                    for direction in &Direction::ALL {
+                        let (xo, yo) = direction.offset();
+                        let from = (x - xo, y - yo);
+                        let player = (x - xo * 2, y - yo * 2);
+                        if self.floor.contains(&from)
+                            && self.floor.contains(&player)
+                            && live.insert(from)
+                        {
+                            queue.push_back(from);
+                        }
+                    }
+                }
+
+                (color, live)
+            })
+            .collect()
+    }
+}
+
+/// Breadth first search over box pushes, so solutions use as few pushes
+/// as possible. Gives up once the time budget runs out.
+pub fn solve(puzzle: &Puzzle, budget: Duration) -> Outcome {
+    let start = Instant::now();
+    let live = puzzle.live_squares();
+
+    let mut boxes = puzzle.boxes.clone();
+    boxes.sort();
+
+    let mut nodes = vec![Node {
+        boxes,
+        player: puzzle.player,
+        parent: 0,
+        push: None,
+    }];
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(0);
+
+    while let Some(index) = queue.pop_front() {
+        if start.elapsed() > budget {
+            return Outcome::GaveUp;
+        }
+
+        let occupied: HashSet<_> = nodes[index].boxes.iter().map(|b| b.0).collect();
+        let reach = reachable(&puzzle.floor, &occupied, nodes[index].player);
+
+        // skip states that were already reached with the player in the same area
+        let area = *reach.iter().min().expect("bad reach");
+        if !seen.insert((nodes[index].boxes.clone(), area)) {
+            continue;
+        }
+
+        if is_solved(&puzzle.goals, &nodes[index].boxes) {
+            let moves = reconstruct(puzzle, &nodes, index);
+            return Outcome::Solved(Solution {
+                moves,
+                states: seen.len(),
+            });
+        }
+
+        let current = nodes[index].boxes.clone();
+        for (i, (tile, color)) in current.iter().enumerate() {
+            for direction in &Direction::ALL {
+                let (xo, yo) = direction.offset();
+                let from = (tile.0 - xo, tile.1 - yo);
+                let to = (tile.0 + xo, tile.1 + yo);
+
+                let is_live = live.get(color).map(|l| l.contains(&to)).unwrap_or(false);
+                if !reach.contains(&from) || occupied.contains(&to) || !is_live {
+                    continue;
+                }
+
+                let mut boxes = current.clone();
+                boxes[i].0 = to;
+                if is_frozen(puzzle, &boxes, to) {
+                    continue;
+                }
+                boxes.sort();
+
+                nodes.push(Node {
+                    boxes,
+                    player: *tile,
+                    parent: index,
+                    push: Some((from, *direction)),
+                });
+                queue.push_back(nodes.len() - 1);
+            }
+        }
+    }
+
+    Outcome::Unsolvable
+}
+
+/// Finds a walking path between two tiles that avoids the boxes.
+pub fn walk(
+    floor: &HashSet<Tile>,
+    occupied: &HashSet<Tile>,
+    from: Tile,
+    to: Tile,
+) -> Option<Vec<Direction>> {
+    let mut came_from = HashMap::new();
+    let mut queue = VecDeque::new();
+    came_from.insert(from, None);
+    queue.push_back(from);
+
+    while let Some(tile) = queue.pop_front() {
+        if tile == to {
+            let mut path = vec![];
+            let mut current = tile;
+            while let Some(Some((previous, direction))) = came_from.get(&current) {
+                path.push(*direction);
+                current = *previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for direction in &Direction::ALL {
+            let (xo, yo) = direction.offset();
+            let next = (tile.0 + xo, tile.1 + yo);
+            if floor.contains(&next) && !occupied.contains(&next) && !came_from.contains_key(&next)
+            {
+                came_from.insert(next, Some((tile, *direction)));
+                queue.push_back(next);
+            }
+        }
+    }
+
+    None
+}
+
+fn reachable(floor: &HashSet<Tile>, occupied: &HashSet<Tile>, from: Tile) -> HashSet<Tile> {
+    let mut reach = HashSet::new();
+    let mut queue = VecDeque::new();
+    reach.insert(from);
+    queue.push_back(from);
+
+    while let Some((x, y)) = queue.pop_front() {
+        for direction in &Direction::ALL {
+            let (xo, yo) = direction.offset();
+            let next = (x + xo, y + yo);
+            if floor.contains(&next) && !occupied.contains(&next) && reach.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    reach
+}
+
+fn reconstruct(puzzle: &Puzzle, nodes: &[Node], index: usize) -> Vec<Move> {
+    // collect the pushes from the start
+    let mut chain = vec![index];
+    let mut current = index;
+    while current != 0 {
+        current = nodes[current].parent;
+        chain.push(current);
+    }
+    chain.reverse();
+
+    // walk to each push and do it
+    let mut moves = vec![];
+    for pair in chain.windows(2) {
+        let (before, after) = (&nodes[pair[0]], &nodes[pair[1]]);
+        let (from, direction) = after.push.expect("bad push");
+        let occupied: HashSet<_> = before.boxes.iter().map(|b| b.0).collect();
+        let path = walk(&puzzle.floor, &occupied, before.player, from).expect("bad path");
+
+        moves.extend(path.into_iter().map(|direction| Move {
+            direction,
+            push: false,
+        }));
+        moves.push(Move {
+            direction,
+            push: true,
+        });
+    }
+
+    moves
+}
+
+fn is_solved(goals: &HashMap<Tile, Option<Color>>, boxes: &[(Tile, Option<Color>)]) -> bool {
+    boxes
+        .iter()
+        .all(|(tile, color)| goals.get(tile) == Some(color))
+}
+
+/// Checks if a pushed box ended up in a 2x2 square of walls and boxes,
+/// where nothing in it can ever move again.
+fn is_frozen(puzzle: &Puzzle, boxes: &[(Tile, Option<Color>)], (x, y): Tile) -> bool {
+    let colors: HashMap<_, _> = boxes.iter().copied().collect();
+    let blocked = |t: &Tile| !puzzle.floor.contains(t) || colors.contains_key(t);
+
+    [(0, 0), (-1, 0), (0, -1), (-1, -1)].iter().any(|(ox, oy)| {
+        let square = [
+            (x + ox, y + oy),
+            (x + ox + 1, y + oy),
+            (x + ox, y + oy + 1),
+            (x + ox + 1, y + oy + 1),
+        ];
+
+        square.iter().all(blocked)
+            && square.iter().any(|t| match colors.get(t) {
+                Some(color) => puzzle.goals.get(t) != Some(color),
+                None => false,
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xsb;
+
+    fn puzzle(text: &str) -> Puzzle {
+        Puzzle::from_level(&xsb::read_each(text).remove(0).1.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn solves_a_small_board() {
+        let puzzle = puzzle("######\n#@ $.#\n######\n");
+        let moves = match solve(&puzzle, Duration::from_secs(1)) {
+            Outcome::Solved(solution) => solution.moves,
+            _ => panic!("not solved"),
+        };
+        let right = |push| Move {
+            direction: Direction::Right,
+            push,
+        };
+        assert_eq!(moves, vec![right(false), right(true)]);
+    }
+
+    #[test]
+    fn dead_board_is_unsolvable() {
+        // the box is stuck in a corner
+        let puzzle = puzzle("#####\n#@ $#\n#.  #\n#####\n");
+        let outcome = solve(&puzzle, Duration::from_secs(1));
+        assert!(matches!(outcome, Outcome::Unsolvable));
+    }
+}
@@ -0,0 +1,55 @@
+use specs::Read;
+use specs::System;
+use specs::Write;
+
+use crate::resources::Achievement;
+use crate::resources::Achievements;
+use crate::resources::GameEvent;
+use crate::resources::GameEvents;
+use crate::resources::Messages;
+use crate::resources::Stats;
+
+/// Unlocks achievements from gameplay events. Runs after `StatsSystem`,
+/// so the stats include this frame.
+pub struct AchievementSystem {}
+
+impl<'s> System<'s> for AchievementSystem {
+    type SystemData = (
+        Write<'s, Achievements>,
+        Write<'s, Messages>,
+        Read<'s, Stats>,
+        Read<'s, GameEvents>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut achievements, mut messages, stats, events) = data;
+
+        let mut earned = vec![];
+        for event in events.iter() {
+            match event {
+                GameEvent::BoxOnGoal { .. } => {
+                    achievements.boxes_placed += 1;
+                    if achievements.boxes_placed >= 100 {
+                        earned.push(Achievement::HundredBoxes);
+                    }
+                }
+                GameEvent::LevelComplete => {
+                    earned.push(Achievement::FirstSolve);
+                    if stats.undos == 0 {
+                        earned.push(Achievement::NoUndo);
+                    }
+                    if stats.blocked == 0 {
+                        earned.push(Achievement::NoBumps);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for achievement in earned {
+            if achievements.unlocked.insert(achievement) {
+                messages.show(format!("achievement unlocked: {}", achievement.name()));
+            }
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use duku::Vec2;
+use specs::Entities;
 use specs::Join;
+use specs::Read;
 use specs::ReadStorage;
 use specs::System;
 use specs::WriteStorage;
@@ -9,6 +11,8 @@ use crate::components::Direction;
 use crate::components::Player;
 use crate::components::Position;
 use crate::components::Sprite;
+use crate::components::Warp;
+use crate::resources::Progress;
 
 pub struct AnimateSystem {
     pub delta_time: f32,
@@ -20,15 +24,32 @@ impl<'s> System<'s> for AnimateSystem {
         WriteStorage<'s, Animations>,
         ReadStorage<'s, Position>,
         ReadStorage<'s, Player>,
+        WriteStorage<'s, Warp>,
+        Read<'s, Progress>,
+        Entities<'s>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut sprites, mut animations, positions, players) = data;
+        let (mut sprites, mut animations, positions, players, mut warps, progress, entities) = data;
+
+        // advance teleport warps
+        let mut warped = vec![];
+        for (warp, entity) in (&mut warps, &entities).join() {
+            warp.time += self.delta_time;
+            if warp.time >= warp.duration {
+                warped.push(entity);
+            }
+        }
+        for entity in warped {
+            warps.remove(entity);
+        }
 
         // do player animation changes
         for (pos, ani, _) in (&positions, &mut animations, &players).join() {
             let prev_animation = ani.current_animation.clone();
-            if pos.offset != Vec2::default() {
+            if progress.complete {
+                ani.current_animation = "special".to_string();
+            } else if pos.offset != Vec2::default() {
                 ani.current_animation = match pos.direction {
                     Direction::Right => "walk-right".to_string(),
                     Direction::Left => "walk-left".to_string(),
@@ -67,8 +88,8 @@ impl<'s> System<'s> for AnimateSystem {
                 // calculate texture part
                 let x = frame_index as u32 % anis.size.x as u32;
                 let y = frame_index as u32 / anis.size.x as u32;
-                let w = spr.texture.read().width() / anis.size.x as u32;
-                let h = spr.texture.read().height() / anis.size.y as u32;
+                let w = anis.frame_size.x as u32;
+                let h = anis.frame_size.y as u32;
 
                 spr.part_pos = Vec2::new((x * w) as f32, (y * h) as f32);
                 spr.part_size = Vec2::new(w as f32, h as f32);
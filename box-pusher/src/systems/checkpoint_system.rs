@@ -0,0 +1,157 @@
+use specs::Entities;
+use specs::Read;
+use specs::ReadStorage;
+use specs::System;
+use specs::Write;
+use specs::WriteStorage;
+
+use crate::components::Movable;
+use crate::components::Player;
+use crate::components::Position;
+use crate::components::PressurePlate;
+use crate::components::Sprite;
+use crate::components::Warp;
+use crate::resources::Button;
+use crate::resources::GameEvent;
+use crate::resources::GameEvents;
+use crate::resources::History;
+use crate::resources::Inputs;
+use crate::resources::Messages;
+use crate::resources::Progress;
+use crate::resources::Snapshot;
+use crate::resources::SLOTS;
+
+/// Restarts the level, and saves or loads checkpoints.
+pub struct CheckpointSystem {}
+
+impl<'s> System<'s> for CheckpointSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        WriteStorage<'s, Position>,
+        WriteStorage<'s, PressurePlate>,
+        WriteStorage<'s, Sprite>,
+        WriteStorage<'s, Warp>,
+        ReadStorage<'s, Player>,
+        ReadStorage<'s, Movable>,
+        Write<'s, History>,
+        Read<'s, Inputs>,
+        Write<'s, GameEvents>,
+        Write<'s, Messages>,
+        Write<'s, Progress>,
+        Entities<'s>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            mut positions,
+            mut plates,
+            mut sprites,
+            mut warps,
+            players,
+            movables,
+            mut history,
+            inputs,
+            mut events,
+            mut messages,
+            mut progress,
+            entities,
+        ) = data;
+
+        // a restart is a fresh attempt, so the undo history goes with it
+        if inputs.keys_typed.contains(&Button::Restart) {
+            if let Some(start) = history.start.clone() {
+                start.restore(&mut positions, &mut plates, &mut sprites, &mut warps);
+                progress.checked = false;
+                history.turns.clear();
+                events.send(GameEvent::Restart);
+            }
+            return;
+        }
+
+        let slot = match SLOTS.iter().position(|b| inputs.keys_typed.contains(b)) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        if inputs.keys_pressed.contains(&Button::Save) {
+            history.checkpoints[slot] = Some(Snapshot::take(
+                &positions, &players, &movables, &plates, &entities,
+            ));
+            messages.show(format!("checkpoint {} saved", slot + 1));
+            events.send(GameEvent::CheckpointSaved { slot });
+            return;
+        }
+
+        // loading takes a turn, so it can be undone
+        match history.checkpoints[slot].clone() {
+            Some(checkpoint) => {
+                let now = Snapshot::take(&positions, &players, &movables, &plates, &entities);
+                history.turns.push(now);
+                checkpoint.restore(&mut positions, &mut plates, &mut sprites, &mut warps);
+                progress.checked = false;
+                events.send(GameEvent::CheckpointLoaded { slot });
+            }
+            None => messages.show(format!("no checkpoint {} saved", slot + 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::config::Config;
+    use crate::level;
+    use crate::resources::Button;
+    use crate::tools;
+    use crate::world::World;
+    use crate::xsb;
+
+    fn press(world: &mut World, buttons: &[Button]) {
+        world.set_buttons(buttons.iter().copied().collect());
+        tools::tick(world, false);
+        world.set_buttons(HashSet::new());
+        while !world.is_settled() {
+            tools::tick(world, false);
+        }
+    }
+
+    #[test]
+    fn save_load_and_restart() {
+        let level = xsb::read_each("########\n#@ $  .#\n########\n")
+            .remove(0)
+            .1
+            .unwrap();
+        let config = Config {
+            mute: true,
+            ..Default::default()
+        };
+        let mut world = World::new(&config).unwrap();
+        level::spawn(&mut world, &level).unwrap();
+        let spawned = world.boards();
+
+        press(&mut world, &[Button::Right]);
+        press(&mut world, &[Button::Save, Button::Slot1]);
+        let saved = world.boards();
+        assert_eq!(saved.len(), 2);
+
+        press(&mut world, &[Button::Right]);
+        let before_load = world.boards().remove(0);
+        assert_ne!(before_load, saved[0]);
+
+        // loading is a turn of its own on top of the history
+        press(&mut world, &[Button::Slot1]);
+        let loaded = world.boards();
+        assert_eq!(loaded[0], saved[0]);
+        assert_eq!(loaded[1], before_load);
+        assert_eq!(loaded.len(), 4);
+
+        // so undo goes back to before the load
+        press(&mut world, &[Button::Undo]);
+        assert_eq!(world.boards().remove(0), before_load);
+
+        // restarting drops the history too
+        press(&mut world, &[Button::Restart]);
+        assert_eq!(world.boards(), spawned);
+    }
+}
@@ -1,49 +1,196 @@
-use duku::Filter;
-use duku::ShapeMode;
-use duku::Target;
+use duku::Rgb;
 use duku::Vec2;
+use specs::Entities;
 use specs::Join;
+use specs::Read;
 use specs::ReadStorage;
 use specs::System;
+use specs::Write;
+use std::cmp::Reverse;
 
+use crate::components::BoxColor;
+use crate::components::GoalColor;
+use crate::components::Layer;
 use crate::components::Position;
 use crate::components::Sprite;
+use crate::components::Warp;
+use crate::particles::Particles;
+use crate::render::Renderer;
+use crate::resources::DrawOrder;
+use crate::resources::HintMarks;
 
 pub struct DrawSystem<'t> {
-    pub target: &'t mut Target,
+    pub renderer: &'t mut dyn Renderer,
     pub view_width: u32,
     pub view_height: u32,
     pub tile_size: u32,
+    pub overlay: Option<Overlay>,
+}
+
+/// Grid and cursor drawn over the level while editing.
+pub struct Overlay {
+    pub width: i32,
+    pub height: i32,
+    pub cursor: (i32, i32),
 }
 
 impl<'t> System<'t> for DrawSystem<'t> {
-    type SystemData = (ReadStorage<'t, Position>, ReadStorage<'t, Sprite>);
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        ReadStorage<'t, Position>,
+        ReadStorage<'t, Layer>,
+        ReadStorage<'t, Sprite>,
+        ReadStorage<'t, Warp>,
+        ReadStorage<'t, BoxColor>,
+        ReadStorage<'t, GoalColor>,
+        Write<'t, DrawOrder>,
+        Read<'t, Particles>,
+        Read<'t, HintMarks>,
+        Entities<'t>,
+    );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (positions, sprites) = data;
+        let (
+            positions,
+            layers,
+            sprites,
+            warps,
+            box_colors,
+            goal_colors,
+            mut order,
+            particles,
+            hint,
+            entities,
+        ) = data;
+
+        self.renderer.translate_y(self.view_height as f32 / -2.0);
+        self.renderer.translate_x(self.view_width as f32 / -2.0);
 
-        self.target.filter(Filter::Nearest);
-        self.target.shape_mode(ShapeMode::TopLeft);
-        self.target.translate_y(self.view_height as f32 / -2.0);
-        self.target.translate_x(self.view_width as f32 / -2.0);
+        // sort keys in pixels, so sprites sliding between tiles sort too
+        let tile = self.tile_size as f32;
+        let keys = (&entities, &positions, &layers, &sprites)
+            .join()
+            .map(|(entity, pos, layer, _)| {
+                let y = if layer.y_sort {
+                    ((pos.y as f32 + pos.offset.y) * tile).round() as i32
+                } else {
+                    0
+                };
+                (entity, (Reverse(layer.index), layer.y_sort, Reverse(y)))
+            })
+            .collect::<Vec<_>>();
+
+        // join order is by entity, so the stable sort keeps ties steady
+        if keys != order.keys {
+            let mut sorted = keys.clone();
+            sorted.sort_by_key(|k| k.1);
+            order.entities = sorted.into_iter().map(|k| k.0).collect();
+            order.keys = keys;
+        }
 
-        let mut draw_data = (&positions, &sprites).join().collect::<Vec<_>>();
-        draw_data.sort_by_key(|k| -(k.0.z as i32));
+        let count = order.entities.len();
+        for (i, entity) in order.entities.iter().enumerate() {
+            let (pos, spr) = match (positions.get(*entity), sprites.get(*entity)) {
+                (Some(pos), Some(spr)) => (pos, spr),
+                _ => continue,
+            };
+            let warp = warps.get(*entity);
+            let box_color = box_colors.get(*entity);
+            let goal_color = goal_colors.get(*entity);
 
-        for (pos, spr) in draw_data {
             let x = (pos.x as f32 + pos.offset.x) * self.tile_size as f32;
             let y = (pos.y as f32 + pos.offset.y) * self.tile_size as f32;
 
-            self.target.push();
-            self.target.translate_z(pos.z as f32);
-            self.target.texture_part(
+            // warped entities grow in from the center
+            let scale = warp.map(|w| (w.time / w.duration).min(1.0)).unwrap_or(1.0);
+            let size = spr.part_size * scale;
+            let centering = (spr.part_size - size) * 0.5;
+
+            self.renderer.push();
+            // depth follows the draw order, from 3 down towards 1
+            self.renderer
+                .translate_z(1.0 + 2.0 * (count - i) as f32 / count as f32);
+            if let Some(color) = box_color.map(|c| c.0).or_else(|| goal_color.map(|c| c.0)) {
+                self.renderer.tint(color.tint());
+            }
+            self.renderer.texture_part(
                 &spr.texture,
-                Vec2::new(x, y),
-                spr.part_size,
+                Vec2::new(x, y) + centering,
+                size,
                 spr.part_pos,
                 spr.part_size,
             );
-            self.target.pop();
+            self.renderer.pop();
+        }
+
+        // particles go in front of every sprite
+        self.renderer.push();
+        self.renderer.translate_z(0.5);
+        self.renderer.no_stroke();
+        for particle in particles.iter() {
+            let size = particle.size();
+            let pos = particle.pos * tile + Vec2::new(-size, size) * 0.5;
+            self.renderer.fill(particle.color());
+            self.renderer.rect(pos, Vec2::new(size, size));
+        }
+        self.renderer.pop();
+
+        // hint ghost path, then the box to push next
+        let center = |(x, y): (i32, i32)| Vec2::new(x as f32 + 0.5, y as f32 - 0.5) * tile;
+        let marker = |size: f32| Vec2::new(-size, size) * 0.5;
+        let hint_color = Rgb::new(255, 221, 0);
+        self.renderer.push();
+        self.renderer.translate_z(0.25);
+        self.renderer.no_stroke();
+        self.renderer.fill(hint_color);
+        for step in &hint.path {
+            self.renderer
+                .rect(center(*step) + marker(2.0), Vec2::new(2.0, 2.0));
+        }
+        if let Some((box_tile, direction)) = hint.push {
+            // a mark on the side the box goes towards
+            let (xo, yo) = direction.offset();
+            let side = Vec2::new(xo as f32, yo as f32) * (tile * 0.5 - 2.0);
+            self.renderer
+                .rect(center(box_tile) + side + marker(3.0), Vec2::new(3.0, 3.0));
+
+            self.renderer.no_fill();
+            self.renderer.stroke(hint_color);
+            self.renderer.stroke_weight(1.0);
+            self.renderer.rect(
+                Vec2::new(box_tile.0 as f32, box_tile.1 as f32) * tile,
+                Vec2::new(tile, tile),
+            );
+        }
+        self.renderer.pop();
+
+        if let Some(overlay) = &self.overlay {
+            let tile = self.tile_size as f32;
+            let width = overlay.width as f32 * tile;
+            let height = overlay.height as f32 * tile;
+
+            self.renderer.push();
+            self.renderer.stroke(Rgb::new(255, 255, 255));
+            self.renderer.stroke_weight(0.5);
+            for x in 0..=overlay.width {
+                let x = x as f32 * tile;
+                self.renderer.line(Vec2::new(x, 0.0), Vec2::new(x, height));
+            }
+            for y in 0..=overlay.height {
+                let y = y as f32 * tile;
+                self.renderer.line(Vec2::new(0.0, y), Vec2::new(width, y));
+            }
+
+            // tiles are drawn down from their top left corner
+            let (x, y) = overlay.cursor;
+            self.renderer.no_fill();
+            self.renderer.stroke(Rgb::new(255, 221, 0));
+            self.renderer.stroke_weight(1.0);
+            self.renderer.rect(
+                Vec2::new(x as f32 * tile, y as f32 * tile),
+                Vec2::new(tile, tile),
+            );
+            self.renderer.pop();
         }
     }
 }
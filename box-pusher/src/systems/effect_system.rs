@@ -0,0 +1,57 @@
+use duku::Rgb;
+use specs::Entities;
+use specs::Join;
+use specs::Read;
+use specs::ReadStorage;
+use specs::System;
+use specs::WriteStorage;
+
+use crate::components::Emitter;
+use crate::components::Player;
+use crate::components::Position;
+use crate::particles::Effect;
+use crate::resources::GameEvent;
+use crate::resources::GameEvents;
+
+/// Starts particle effects for gameplay events.
+pub struct EffectSystem {}
+
+impl<'s> System<'s> for EffectSystem {
+    type SystemData = (
+        ReadStorage<'s, Position>,
+        ReadStorage<'s, Player>,
+        WriteStorage<'s, Emitter>,
+        Read<'s, GameEvents>,
+        Entities<'s>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (positions, players, mut emitters, events, entities) = data;
+
+        let mut started = vec![];
+        for event in events.iter() {
+            match *event {
+                GameEvent::PushBlocked {
+                    tile,
+                    against_box: true,
+                    ..
+                } => started.push(Emitter::at_tile(Effect::dust(), tile.0, tile.1)),
+                GameEvent::BoxOnGoal { tile, color, .. } => {
+                    let tint = color.map(|c| c.tint()).unwrap_or(Rgb::new(255, 240, 110));
+                    started.push(Emitter::at_tile(Effect::sparkle(tint), tile.0, tile.1));
+                }
+                GameEvent::LevelComplete => {
+                    // celebrate over the player
+                    for (pos, _) in (&positions, &players).join() {
+                        started.push(Emitter::at_tile(Effect::confetti(), pos.x, pos.y));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for emitter in started {
+            entities.build_entity().with(emitter, &mut emitters).build();
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use duku::Vec2;
+use specs::Entities;
+use specs::Join;
+use specs::ReadStorage;
+use specs::System;
+use specs::Write;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::components::BoxColor;
+use crate::components::Goal;
+use crate::components::GoalColor;
+use crate::components::Movable;
+use crate::components::Position;
+use crate::resources::GameEvent;
+use crate::resources::GameEvents;
+use crate::resources::Progress;
+
+pub struct GoalSystem {}
+
+impl<'s> System<'s> for GoalSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        ReadStorage<'s, Position>,
+        ReadStorage<'s, Movable>,
+        ReadStorage<'s, BoxColor>,
+        ReadStorage<'s, Goal>,
+        ReadStorage<'s, GoalColor>,
+        Write<'s, Progress>,
+        Write<'s, GameEvents>,
+        Entities<'s>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            positions,
+            movables,
+            box_colors,
+            goals,
+            goal_colors,
+            mut progress,
+            mut events,
+            entities,
+        ) = data;
+
+        // map goal tiles to their color
+        let goal_tiles: HashMap<_, _> = (&positions, &goals, goal_colors.maybe())
+            .join()
+            .map(|(pos, _, color)| ((pos.x, pos.y), color.map(|c| c.0)))
+            .collect();
+
+        // every box has to rest on a goal of the same color
+        let mut any_boxes = false;
+        let mut all_placed = true;
+        let mut placed = HashSet::new();
+        for (pos, _, color, entity) in (&positions, &movables, box_colors.maybe(), &entities).join()
+        {
+            any_boxes = true;
+            let color = color.map(|c| c.0);
+            if pos.offset != Vec2::default() || goal_tiles.get(&(pos.x, pos.y)) != Some(&color) {
+                all_placed = false;
+                continue;
+            }
+
+            // boxes that start on goals or come back with a restored board
+            // were not placed by the player
+            placed.insert(entity);
+            if progress.checked && !progress.placed.contains(&entity) {
+                events.send(GameEvent::BoxOnGoal {
+                    entity,
+                    tile: (pos.x, pos.y),
+                    color,
+                });
+            }
+        }
+
+        if progress.checked {
+            for entity in progress.placed.difference(&placed) {
+                if let Some(pos) = positions.get(*entity) {
+                    events.send(GameEvent::BoxOffGoal {
+                        entity: *entity,
+                        tile: (pos.x, pos.y),
+                    });
+                }
+            }
+        }
+
+        let complete = any_boxes && all_placed;
+        if progress.checked && complete && !progress.complete {
+            events.send(GameEvent::LevelComplete);
+        }
+
+        progress.complete = complete;
+        progress.placed = placed;
+        progress.checked = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::audio::AudioEvent;
+    use crate::config::Config;
+    use crate::level;
+    use crate::resources::Button;
+    use crate::tools;
+    use crate::world::World;
+    use crate::xsb;
+
+    fn press(world: &mut World, buttons: &[Button]) {
+        world.set_buttons(buttons.iter().copied().collect());
+        tools::tick(world, false);
+        world.set_buttons(HashSet::new());
+        while !world.is_settled() {
+            tools::tick(world, false);
+        }
+    }
+
+    fn count(world: &World, sound: &str) -> usize {
+        let sound = AudioEvent::Sound(sound.to_string());
+        world.audio_events().iter().filter(|e| **e == sound).count()
+    }
+
+    #[test]
+    fn restores_are_not_placements() {
+        let level = xsb::read_each("######\n#@$. #\n######\n")
+            .remove(0)
+            .1
+            .unwrap();
+        let config = Config {
+            mute: true,
+            ..Default::default()
+        };
+        let mut world = World::new(&config).unwrap();
+        world.load_sounds("assets/sounds.json").unwrap();
+        level::spawn(&mut world, &level).unwrap();
+
+        press(&mut world, &[Button::Right]);
+        press(&mut world, &[Button::Save, Button::Slot1]);
+        assert_eq!(count(&world, "goal"), 1);
+        assert_eq!(count(&world, "complete"), 1);
+
+        // taking the box off by restart, load, undo and restart again
+        press(&mut world, &[Button::Restart]);
+        press(&mut world, &[Button::Slot1]);
+        press(&mut world, &[Button::Undo]);
+        press(&mut world, &[Button::Slot1]);
+        press(&mut world, &[Button::Restart]);
+        assert_eq!(count(&world, "goal"), 1);
+        assert_eq!(count(&world, "ungoal"), 0);
+        assert_eq!(count(&world, "complete"), 1);
+
+        // the player placing it again still counts
+        press(&mut world, &[Button::Right]);
+        assert_eq!(count(&world, "goal"), 2);
+        assert_eq!(count(&world, "complete"), 2);
+    }
+}
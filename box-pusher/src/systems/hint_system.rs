@@ -0,0 +1,31 @@
+use specs::Read;
+use specs::System;
+use specs::Write;
+
+use crate::resources::GameEvent;
+use crate::resources::GameEvents;
+use crate::resources::HintMarks;
+
+/// Takes the hint off the board once the player moves or goes back.
+pub struct HintSystem {}
+
+impl<'s> System<'s> for HintSystem {
+    type SystemData = (Write<'s, HintMarks>, Read<'s, GameEvents>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut hint, events) = data;
+
+        let moved = events.iter().any(|e| {
+            matches!(
+                e,
+                GameEvent::PlayerMoved { .. }
+                    | GameEvent::Undo
+                    | GameEvent::Restart
+                    | GameEvent::CheckpointLoaded { .. }
+            )
+        });
+        if moved {
+            *hint = HintMarks::default();
+        }
+    }
+}
@@ -20,6 +20,7 @@ impl<'a> System<'a> for InputSystem<'a> {
 
     fn run(&mut self, data: Self::SystemData) {
         let mut inputs = data;
+        let previous = inputs.keys_pressed.clone();
 
         // get the active gamepad if exists
         let mut gamepad_id = None;
@@ -49,6 +50,54 @@ impl<'a> System<'a> for InputSystem<'a> {
         } else {
             inputs.keys_pressed.remove(&Button::Right);
         }
+        if gamepad_pressed(&gamepad, GButton::South) || self.events.is_key_pressed(Key::Space) {
+            inputs.keys_pressed.insert(Button::Action);
+        } else {
+            inputs.keys_pressed.remove(&Button::Action);
+        }
+        if gamepad_pressed(&gamepad, GButton::West) || self.events.is_key_pressed(Key::Z) {
+            inputs.keys_pressed.insert(Button::Undo);
+        } else {
+            inputs.keys_pressed.remove(&Button::Undo);
+        }
+        if gamepad_pressed(&gamepad, GButton::North) || self.events.is_key_pressed(Key::H) {
+            inputs.keys_pressed.insert(Button::Hint);
+        } else {
+            inputs.keys_pressed.remove(&Button::Hint);
+        }
+        if gamepad_pressed(&gamepad, GButton::Select) || self.events.is_key_pressed(Key::R) {
+            inputs.keys_pressed.insert(Button::Restart);
+        } else {
+            inputs.keys_pressed.remove(&Button::Restart);
+        }
+
+        // shift or the right bumper with a slot saves, the slot alone loads
+        let shift =
+            self.events.is_key_pressed(Key::LShift) || self.events.is_key_pressed(Key::RShift);
+        if gamepad_pressed(&gamepad, GButton::RightTrigger) || shift {
+            inputs.keys_pressed.insert(Button::Save);
+        } else {
+            inputs.keys_pressed.remove(&Button::Save);
+        }
+        if gamepad_pressed(&gamepad, GButton::LeftTrigger) || self.events.is_key_pressed(Key::Key1)
+        {
+            inputs.keys_pressed.insert(Button::Slot1);
+        } else {
+            inputs.keys_pressed.remove(&Button::Slot1);
+        }
+        if self.events.is_key_pressed(Key::Key2) {
+            inputs.keys_pressed.insert(Button::Slot2);
+        } else {
+            inputs.keys_pressed.remove(&Button::Slot2);
+        }
+        if self.events.is_key_pressed(Key::Key3) {
+            inputs.keys_pressed.insert(Button::Slot3);
+        } else {
+            inputs.keys_pressed.remove(&Button::Slot3);
+        }
+
+        // buttons that went down this frame
+        inputs.keys_typed = inputs.keys_pressed.difference(&previous).copied().collect();
     }
 }
 
@@ -0,0 +1,30 @@
+use specs::System;
+use specs::Write;
+
+use crate::resources::Messages;
+
+// seconds a message stays up, and how many fit on screen
+const MESSAGE_TIME: f32 = 4.0;
+const MAX_MESSAGES: usize = 4;
+
+/// Ages messages for the player and drops them once they have been up
+/// long enough, or newer ones push them off.
+pub struct MessageSystem {
+    pub delta_time: f32,
+}
+
+impl<'s> System<'s> for MessageSystem {
+    type SystemData = Write<'s, Messages>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let mut messages = data;
+
+        for message in &mut messages.messages {
+            message.age += self.delta_time;
+        }
+        messages.messages.retain(|m| m.age < MESSAGE_TIME);
+
+        let extra = messages.messages.len().saturating_sub(MAX_MESSAGES);
+        messages.messages.drain(..extra);
+    }
+}
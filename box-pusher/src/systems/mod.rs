@@ -1,9 +1,30 @@
+mod achievement_system;
 mod animate_system;
+mod checkpoint_system;
 mod draw_system;
+mod effect_system;
+mod goal_system;
+mod hint_system;
 mod input_system;
+mod message_system;
 mod move_system;
+mod particle_system;
+mod stats_system;
+mod trigger_system;
+mod undo_system;
 
+pub use achievement_system::AchievementSystem;
 pub use animate_system::AnimateSystem;
+pub use checkpoint_system::CheckpointSystem;
 pub use draw_system::DrawSystem;
+pub use draw_system::Overlay;
+pub use effect_system::EffectSystem;
+pub use goal_system::GoalSystem;
+pub use hint_system::HintSystem;
 pub use input_system::InputSystem;
+pub use message_system::MessageSystem;
 pub use move_system::MoveSystem;
+pub use particle_system::ParticleSystem;
+pub use stats_system::StatsSystem;
+pub use trigger_system::TriggerSystem;
+pub use undo_system::UndoSystem;
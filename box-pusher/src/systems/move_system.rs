@@ -5,19 +5,48 @@ use specs::Join;
 use specs::Read;
 use specs::ReadStorage;
 use specs::System;
+use specs::Write;
 use specs::WriteStorage;
 use std::collections::HashMap;
+use std::collections::HashSet;
 
+use crate::components::Conveyor;
 use crate::components::Direction;
 use crate::components::Immovable;
 use crate::components::Movable;
+use crate::components::OneWay;
 use crate::components::Player;
 use crate::components::Position;
+use crate::components::PressurePlate;
+use crate::components::Teleporter;
+use crate::components::Warp;
 use crate::resources::Button;
+use crate::resources::GameEvent;
+use crate::resources::GameEvents;
+use crate::resources::History;
 use crate::resources::Inputs;
+use crate::resources::Rules;
+use crate::resources::Snapshot;
 
 pub struct MoveSystem {}
 
+type Tile = (i32, i32);
+
+struct Board {
+    immov: HashMap<Tile, Entity>,
+    mov: HashMap<Tile, Entity>,
+    tele: HashMap<Tile, Tile>,
+    oneway: HashMap<Tile, Direction>,
+    conveyors: HashMap<Tile, Direction>,
+}
+
+impl Board {
+    fn can_enter(&self, tile: Tile, direction: Direction) -> bool {
+        !self.immov.contains_key(&tile)
+            && !matches!(self.oneway.get(&tile), Some(d) if *d != direction)
+    }
+}
+
 impl<'s> System<'s> for MoveSystem {
     #[allow(clippy::type_complexity)]
     type SystemData = (
@@ -25,23 +54,64 @@ impl<'s> System<'s> for MoveSystem {
         ReadStorage<'s, Player>,
         ReadStorage<'s, Immovable>,
         ReadStorage<'s, Movable>,
+        ReadStorage<'s, Teleporter>,
+        ReadStorage<'s, OneWay>,
+        ReadStorage<'s, Conveyor>,
+        ReadStorage<'s, PressurePlate>,
+        WriteStorage<'s, Warp>,
         Read<'s, Inputs>,
+        Read<'s, Rules>,
+        Write<'s, History>,
+        Write<'s, GameEvents>,
         Entities<'s>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut positions, players, immovables, movables, inputs, entities) = data;
+        let (
+            mut positions,
+            players,
+            immovables,
+            movables,
+            teleporters,
+            oneways,
+            conveyors,
+            plates,
+            mut warps,
+            inputs,
+            rules,
+            mut history,
+            mut events,
+            entities,
+        ) = data;
 
         // build a tile reference map
-        let immov: HashMap<_, _> = (&positions, &immovables, &entities)
-            .join()
-            .map(|(pos, _, i)| ((pos.x, pos.y), i))
-            .collect();
+        let board = Board {
+            immov: (&positions, &immovables, &entities)
+                .join()
+                .map(|(pos, _, i)| ((pos.x, pos.y), i))
+                .collect(),
+            mov: (&positions, &movables, &entities)
+                .join()
+                .map(|(pos, _, i)| ((pos.x, pos.y), i))
+                .collect(),
+            tele: (&positions, &teleporters)
+                .join()
+                .filter_map(|(pos, t)| {
+                    let target = positions.get(t.target?)?;
+                    Some(((pos.x, pos.y), (target.x, target.y)))
+                })
+                .collect(),
+            oneway: (&positions, &oneways)
+                .join()
+                .map(|(pos, o)| ((pos.x, pos.y), o.0))
+                .collect(),
+            conveyors: (&positions, &conveyors)
+                .join()
+                .map(|(pos, c)| ((pos.x, pos.y), c.0))
+                .collect(),
+        };
 
-        let mov: HashMap<_, _> = (&positions, &movables, &entities)
-            .join()
-            .map(|(pos, _, i)| ((pos.x, pos.y), i))
-            .collect();
+        let pulling = inputs.keys_pressed.contains(&Button::Action);
 
         // do player position changes
         let mut moving_entities = vec![];
@@ -50,113 +120,535 @@ impl<'s> System<'s> for MoveSystem {
                 let no_x = pos.offset.x.abs() == 0.0;
                 let no_y = pos.offset.y.abs() == 0.0;
 
-                if inputs.keys_pressed.contains(&Button::Up) && no_x {
-                    if can_move(&immov, &mov, Direction::Up, pos.x, pos.y) {
-                        moving_entities.push((player, Direction::Up));
+                let direction = [
+                    (Button::Up, Direction::Up, no_x),
+                    (Button::Down, Direction::Down, no_x),
+                    (Button::Left, Direction::Left, no_y),
+                    (Button::Right, Direction::Right, no_y),
+                ]
+                .iter()
+                .find(|(button, _, free)| *free && inputs.keys_pressed.contains(button))
+                .map(|(button, direction, _)| (*button, *direction));
 
-                        // check if should push a thing
-                        if let Some(entity) = mov.get(&(pos.x, pos.y + 1)) {
-                            moving_entities.push((*entity, Direction::Up));
+                if let Some((button, direction)) = direction {
+                    match resolve_move(&board, &rules, player, direction, pulling, pos.x, pos.y) {
+                        Some(moves) => moving_entities.extend(moves),
+                        // once per key press instead of every frame it is held
+                        None if inputs.keys_typed.contains(&button) => {
+                            let (xo, yo) = direction.offset();
+                            let tile = (pos.x + xo, pos.y + yo);
+                            events.send(GameEvent::PushBlocked {
+                                tile,
+                                direction,
+                                against_box: board.mov.contains_key(&tile),
+                            });
                         }
-                    } else {
-                        // bump sound
-                    }
-                }
-                if inputs.keys_pressed.contains(&Button::Down) && no_x {
-                    if can_move(&immov, &mov, Direction::Down, pos.x, pos.y) {
-                        moving_entities.push((player, Direction::Down));
-
-                        // check if should push a thing
-                        if let Some(entity) = mov.get(&(pos.x, pos.y - 1)) {
-                            moving_entities.push((*entity, Direction::Down));
-                        }
-                    } else {
-                        // bump sound
-                    }
-                }
-                if inputs.keys_pressed.contains(&Button::Left) && no_y {
-                    if can_move(&immov, &mov, Direction::Left, pos.x, pos.y) {
-                        moving_entities.push((player, Direction::Left));
-
-                        // check if should push a thing
-                        if let Some(entity) = mov.get(&(pos.x - 1, pos.y)) {
-                            moving_entities.push((*entity, Direction::Left));
-                        }
-                    } else {
-                        // bump sound
-                    }
-                }
-                if inputs.keys_pressed.contains(&Button::Right) && no_y {
-                    if can_move(&immov, &mov, Direction::Right, pos.x, pos.y) {
-                        moving_entities.push((player, Direction::Right));
-
-                        // check if should push a thing
-                        if let Some(entity) = mov.get(&(pos.x + 1, pos.y)) {
-                            moving_entities.push((*entity, Direction::Right));
-                        }
-                    } else {
-                        // bump sound
+                        None => (),
                     }
                 }
             }
         }
 
+        if moving_entities.is_empty() {
+            slide(&mut positions);
+            return;
+        }
+
+        // remember the board for undo
+        history.turns.push(Snapshot::take(
+            &positions, &players, &movables, &plates, &entities,
+        ));
+
         // move all entities that should be moved
-        for (entity, direction) in moving_entities {
-            let mut pos = positions.get_mut(entity).expect("bad entity");
-            pos.direction = direction;
-            match direction {
-                Direction::Up => {
-                    pos.y += 1;
-                    pos.offset += Vec2::down();
-                }
-                Direction::Down => {
-                    pos.y -= 1;
-                    pos.offset += Vec2::up();
+        for (entity, direction, to) in moving_entities {
+            let pos = positions.get(entity).expect("bad entity");
+            let from = (pos.x, pos.y);
+            events.send(if players.contains(entity) {
+                GameEvent::PlayerMoved {
+                    from,
+                    to,
+                    direction,
                 }
-                Direction::Left => {
-                    pos.x -= 1;
-                    pos.offset += Vec2::right();
+            } else {
+                GameEvent::BoxPushed {
+                    entity,
+                    from,
+                    to,
+                    direction,
                 }
-                Direction::Right => {
-                    pos.x += 1;
-                    pos.offset += Vec2::left();
-                }
-            }
+            });
+            move_entity(&mut positions, &mut warps, entity, direction, to);
         }
 
-        // move objects that have an offset
-        for pos in (&mut positions).join() {
-            if pos.offset != Vec2::default() {
-                let dir = -pos.offset.unit();
-                let dist = dir * 0.1;
+        // environment step after the player took a turn
+        let occupied = (&positions, &entities)
+            .join()
+            .filter(|(_, e)| players.contains(*e) || movables.contains(*e))
+            .map(|(pos, e)| ((pos.x, pos.y), e))
+            .collect();
+        for (entity, direction, to) in convey(&board, occupied) {
+            move_entity(&mut positions, &mut warps, entity, direction, to);
+        }
 
-                if pos.offset.length() >= dist.length() {
-                    pos.offset += dist;
-                } else {
-                    pos.offset = Vec2::default();
-                }
+        slide(&mut positions);
+    }
+}
+
+fn move_entity(
+    positions: &mut WriteStorage<'_, Position>,
+    warps: &mut WriteStorage<'_, Warp>,
+    entity: Entity,
+    direction: Direction,
+    (x, y): Tile,
+) {
+    let pos = positions.get_mut(entity).expect("bad entity");
+    pos.direction = direction;
+
+    // warp to teleporter exits instead of sliding
+    let (xo, yo) = direction.offset();
+    if (x, y) != (pos.x + xo, pos.y + yo) {
+        pos.x = x;
+        pos.y = y;
+        pos.offset = Vec2::default();
+        warps
+            .insert(
+                entity,
+                Warp {
+                    time: 0.0,
+                    duration: 0.3,
+                },
+            )
+            .expect("bad entity");
+        return;
+    }
+
+    match direction {
+        Direction::Up => {
+            pos.y += 1;
+            pos.offset += Vec2::down();
+        }
+        Direction::Down => {
+            pos.y -= 1;
+            pos.offset += Vec2::up();
+        }
+        Direction::Left => {
+            pos.x -= 1;
+            pos.offset += Vec2::right();
+        }
+        Direction::Right => {
+            pos.x += 1;
+            pos.offset += Vec2::left();
+        }
+    }
+}
+
+fn slide(positions: &mut WriteStorage<'_, Position>) {
+    // move objects that have an offset
+    for pos in positions.join() {
+        if pos.offset != Vec2::default() {
+            let dir = -pos.offset.unit();
+            let dist = dir * 0.1;
+
+            if pos.offset.length() >= dist.length() {
+                pos.offset += dist;
+            } else {
+                pos.offset = Vec2::default();
             }
         }
     }
 }
 
-fn can_move(
-    immov: &HashMap<(i32, i32), Entity>,
-    mov: &HashMap<(i32, i32), Entity>,
+/// Works out everything that moves when the player steps in a direction.
+/// Returns the destination tile of each moving entity or `None` if blocked.
+fn resolve_move(
+    board: &Board,
+    rules: &Rules,
+    player: Entity,
     direction: Direction,
+    pulling: bool,
     x: i32,
     y: i32,
-) -> bool {
-    let (xo, yo) = match direction {
-        Direction::Up => (0, 1),
-        Direction::Down => (0, -1),
-        Direction::Right => (1, 0),
-        Direction::Left => (-1, 0),
+) -> Option<Vec<(Entity, Direction, Tile)>> {
+    let (xo, yo) = direction.offset();
+
+    // collect the line of boxes in front of the player
+    let mut pushed = vec![];
+    let mut tile = (x + xo, y + yo);
+    while let Some(entity) = board.mov.get(&tile) {
+        pushed.push((*entity, tile));
+        tile = (tile.0 + xo, tile.1 + yo);
+    }
+
+    if !board.can_enter(tile, direction) || pushed.len() > rules.chain as usize {
+        return None;
+    }
+
+    // check if should pull a thing
+    let pulled = if pulling && rules.pull {
+        board
+            .mov
+            .get(&(x - xo, y - yo))
+            .map(|e| (*e, (x - xo, y - yo)))
+    } else {
+        None
     };
 
-    !(immov.contains_key(&(x + xo, y + yo))
-        || (mov.contains_key(&(x + xo, y + yo))
-            && (immov.contains_key(&(x + xo * 2, y + yo * 2))
-                || mov.contains_key(&(x + xo * 2, y + yo * 2)))))
+    let moved = pushed.len() + pulled.iter().count();
+    if matches!(rules.strength, Some(s) if moved > s as usize) {
+        return None;
+    }
+
+    // move everything front to back, so tiles get vacated before reuse
+    let mut vacated = HashSet::new();
+    let mut claimed = HashSet::new();
+    let movers = pushed
+        .into_iter()
+        .rev()
+        .chain(Some((player, (x, y))))
+        .chain(pulled);
+
+    let mut moves = vec![];
+    for (entity, (ex, ey)) in movers {
+        let next = (ex + xo, ey + yo);
+        if !board.can_enter(next, direction) {
+            return None;
+        }
+
+        let to = arrive(&board.tele, next, |t| {
+            !board.immov.contains_key(&t)
+                && (!board.mov.contains_key(&t) || vacated.contains(&t))
+                && !claimed.contains(&t)
+        });
+
+        vacated.insert((ex, ey));
+        claimed.insert(to);
+        moves.push((entity, direction, to));
+    }
+
+    Some(moves)
+}
+
+/// Moves everything resting on a conveyor one tile along it.
+fn convey(board: &Board, mut occupied: HashMap<Tile, Entity>) -> Vec<(Entity, Direction, Tile)> {
+    let mut waiting: Vec<_> = occupied
+        .iter()
+        .filter_map(|(tile, e)| board.conveyors.get(tile).map(|d| (*e, *tile, *d)))
+        .collect();
+
+    // keep going while something moved, so lines of things can follow each other
+    let mut moves = vec![];
+    loop {
+        let count = waiting.len();
+        waiting.retain(|(entity, tile, direction)| {
+            let (xo, yo) = direction.offset();
+            let next = (tile.0 + xo, tile.1 + yo);
+            if !board.can_enter(next, *direction) || occupied.contains_key(&next) {
+                return true;
+            }
+
+            let to = arrive(&board.tele, next, |t| {
+                !board.immov.contains_key(&t) && !occupied.contains_key(&t)
+            });
+            occupied.remove(tile);
+            occupied.insert(to, *entity);
+            moves.push((*entity, *direction, to));
+            false
+        });
+
+        if waiting.len() == count {
+            break;
+        }
+    }
+
+    moves
+}
+
+fn arrive(tele: &HashMap<Tile, Tile>, tile: Tile, is_free: impl Fn(Tile) -> bool) -> Tile {
+    match tele.get(&tile) {
+        Some(exit) if is_free(*exit) => *exit,
+        _ => tile,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use specs::Builder;
+    use specs::World as SpecsWorld;
+    use specs::WorldExt;
+
+    use super::*;
+
+    type Moves = Option<Vec<(Entity, Direction, Tile)>>;
+
+    // a row of tiles, the player stands on (0, 0) and steps right
+    struct Row {
+        board: Board,
+        player: Entity,
+        boxes: HashMap<i32, Entity>,
+    }
+
+    impl Row {
+        fn new(boxes: &[i32], wall: Option<i32>) -> Self {
+            let mut world = SpecsWorld::new();
+            let mut board = Board {
+                immov: HashMap::new(),
+                mov: HashMap::new(),
+                tele: HashMap::new(),
+                oneway: HashMap::new(),
+                conveyors: HashMap::new(),
+            };
+
+            let player = world.create_entity().build();
+            let boxes: HashMap<_, _> = boxes
+                .iter()
+                .map(|x| (*x, world.create_entity().build()))
+                .collect();
+            for (x, entity) in &boxes {
+                board.mov.insert((*x, 0), *entity);
+            }
+            if let Some(x) = wall {
+                board.immov.insert((x, 0), world.create_entity().build());
+            }
+
+            Self {
+                board,
+                player,
+                boxes,
+            }
+        }
+
+        fn step(&self, pull: bool, chain: u32, strength: Option<u32>, pulling: bool) -> Moves {
+            let rules = Rules {
+                pull,
+                chain,
+                strength,
+            };
+            resolve_move(
+                &self.board,
+                &rules,
+                self.player,
+                Direction::Right,
+                pulling,
+                0,
+                0,
+            )
+        }
+
+        // the entity on tile x moves to tile `to`
+        fn to(&self, x: i32, to: i32) -> (Entity, Direction, Tile) {
+            let entity = if x == 0 { self.player } else { self.boxes[&x] };
+            (entity, Direction::Right, (to, 0))
+        }
+
+        // a pad on tile x that leads to tile `exit`
+        fn teleporter(&mut self, x: i32, exit: i32) {
+            self.board.tele.insert((x, 0), (exit, 0));
+        }
+
+        // the environment step, with the player still on (0, 0)
+        fn convey(&self) -> Vec<(Entity, Direction, Tile)> {
+            let mut occupied: HashMap<_, _> =
+                self.boxes.iter().map(|(x, e)| ((*x, 0), *e)).collect();
+            occupied.insert((0, 0), self.player);
+            let mut moves = convey(&self.board, occupied);
+            moves.sort_by_key(|m| m.2);
+            moves
+        }
+    }
+
+    #[test]
+    fn walk() {
+        let row = Row::new(&[], None);
+        assert_eq!(row.step(false, 1, None, false), Some(vec![row.to(0, 1)]));
+    }
+
+    #[test]
+    fn walk_into_wall() {
+        let row = Row::new(&[], Some(1));
+        assert_eq!(row.step(false, 1, None, false), None);
+    }
+
+    #[test]
+    fn push() {
+        let row = Row::new(&[1], None);
+        let moves = Some(vec![row.to(1, 2), row.to(0, 1)]);
+        assert_eq!(row.step(false, 1, None, false), moves);
+    }
+
+    #[test]
+    fn push_into_wall() {
+        let row = Row::new(&[1], Some(2));
+        assert_eq!(row.step(false, 1, None, false), None);
+    }
+
+    #[test]
+    fn chain() {
+        let row = Row::new(&[1, 2], None);
+        assert_eq!(row.step(false, 1, None, false), None);
+
+        let moves = Some(vec![row.to(2, 3), row.to(1, 2), row.to(0, 1)]);
+        assert_eq!(row.step(false, 2, None, false), moves);
+    }
+
+    #[test]
+    fn chain_into_wall() {
+        let row = Row::new(&[1, 2], Some(3));
+        assert_eq!(row.step(false, 2, None, false), None);
+    }
+
+    #[test]
+    fn strength() {
+        let row = Row::new(&[1, 2, 3], None);
+        assert_eq!(row.step(false, 3, Some(2), false), None);
+
+        let moves = Some(vec![row.to(3, 4), row.to(2, 3), row.to(1, 2), row.to(0, 1)]);
+        assert_eq!(row.step(false, 3, Some(3), false), moves);
+    }
+
+    #[test]
+    fn pull() {
+        let row = Row::new(&[-1], None);
+        let moves = Some(vec![row.to(0, 1), row.to(-1, 0)]);
+        assert_eq!(row.step(true, 1, None, true), moves);
+
+        // needs both the rule and the button
+        let alone = Some(vec![row.to(0, 1)]);
+        assert_eq!(row.step(true, 1, None, false), alone);
+        assert_eq!(row.step(false, 1, None, true), alone);
+    }
+
+    #[test]
+    fn pull_into_wall() {
+        let row = Row::new(&[-1], Some(1));
+        assert_eq!(row.step(true, 1, None, true), None);
+    }
+
+    #[test]
+    fn pull_while_pushing() {
+        let row = Row::new(&[-1, 1], None);
+        let moves = Some(vec![row.to(1, 2), row.to(0, 1), row.to(-1, 0)]);
+        assert_eq!(row.step(true, 1, None, true), moves);
+
+        // the pulled box counts towards strength
+        assert_eq!(row.step(true, 1, Some(1), true), None);
+        assert_eq!(row.step(true, 1, Some(2), true), moves);
+    }
+
+    #[test]
+    fn pull_while_pushing_a_chain() {
+        let row = Row::new(&[-1, 1, 2], None);
+        assert_eq!(row.step(true, 1, None, true), None);
+
+        let moves = Some(vec![
+            row.to(2, 3),
+            row.to(1, 2),
+            row.to(0, 1),
+            row.to(-1, 0),
+        ]);
+        assert_eq!(row.step(true, 2, Some(3), true), moves);
+        assert_eq!(row.step(true, 2, Some(2), true), None);
+    }
+
+    #[test]
+    fn push_through_teleporter() {
+        let mut row = Row::new(&[1], None);
+        row.teleporter(2, 5);
+        let moves = Some(vec![row.to(1, 5), row.to(0, 1)]);
+        assert_eq!(row.step(false, 1, None, false), moves);
+    }
+
+    #[test]
+    fn teleporter_exit_taken() {
+        // a box standing on the exit keeps the pushed box on the pad
+        let mut row = Row::new(&[1, 5], None);
+        row.teleporter(2, 5);
+        let moves = Some(vec![row.to(1, 2), row.to(0, 1)]);
+        assert_eq!(row.step(false, 1, None, false), moves);
+
+        let mut row = Row::new(&[1], Some(5));
+        row.teleporter(2, 5);
+        assert_eq!(row.step(false, 1, None, false), moves);
+    }
+
+    #[test]
+    fn teleporter_exit_claimed_this_turn() {
+        // the exit is where the player is stepping, so the box stays
+        let mut row = Row::new(&[1], None);
+        row.teleporter(2, 1);
+        let moves = Some(vec![row.to(1, 2), row.to(0, 1)]);
+        assert_eq!(row.step(false, 1, None, false), moves);
+
+        // or where the box behind is pushed to
+        let mut row = Row::new(&[1, 2], None);
+        row.teleporter(3, 2);
+        let moves = Some(vec![row.to(2, 3), row.to(1, 2), row.to(0, 1)]);
+        assert_eq!(row.step(false, 2, None, false), moves);
+    }
+
+    #[test]
+    fn player_teleports() {
+        let mut row = Row::new(&[], None);
+        row.teleporter(1, 5);
+        assert_eq!(row.step(false, 1, None, false), Some(vec![row.to(0, 5)]));
+    }
+
+    #[test]
+    fn one_way() {
+        let mut row = Row::new(&[1], None);
+        row.board.oneway.insert((1, 0), Direction::Right);
+        row.board.oneway.insert((2, 0), Direction::Right);
+        let moves = Some(vec![row.to(1, 2), row.to(0, 1)]);
+        assert_eq!(row.step(false, 1, None, false), moves);
+
+        // neither the player nor boxes get in against it
+        row.board.oneway.insert((1, 0), Direction::Left);
+        assert_eq!(row.step(false, 1, None, false), None);
+        row.board.oneway.insert((1, 0), Direction::Right);
+        row.board.oneway.insert((2, 0), Direction::Up);
+        assert_eq!(row.step(false, 1, None, false), None);
+    }
+
+    #[test]
+    fn conveyor() {
+        let mut row = Row::new(&[1], None);
+        row.board.conveyors.insert((1, 0), Direction::Right);
+        assert_eq!(row.convey(), vec![row.to(1, 2)]);
+
+        // things off conveyors stay put
+        row.board.conveyors.clear();
+        assert_eq!(row.convey(), vec![]);
+    }
+
+    #[test]
+    fn conveyor_line_follows() {
+        // the front one moves first, whichever order they are found in
+        let mut row = Row::new(&[1, 2, 3], None);
+        for x in 0..4 {
+            row.board.conveyors.insert((x, 0), Direction::Right);
+        }
+        let moves = vec![row.to(0, 1), row.to(1, 2), row.to(2, 3), row.to(3, 4)];
+        assert_eq!(row.convey(), moves);
+    }
+
+    #[test]
+    fn conveyor_blocked() {
+        let mut row = Row::new(&[1, 2], Some(3));
+        row.board.conveyors.insert((1, 0), Direction::Right);
+        row.board.conveyors.insert((2, 0), Direction::Right);
+        assert_eq!(row.convey(), vec![]);
+
+        // a one way against the belt stops it too
+        let mut row = Row::new(&[1], None);
+        row.board.conveyors.insert((1, 0), Direction::Right);
+        row.board.oneway.insert((2, 0), Direction::Left);
+        assert_eq!(row.convey(), vec![]);
+    }
+
+    #[test]
+    fn conveyor_into_teleporter() {
+        let mut row = Row::new(&[1], None);
+        row.board.conveyors.insert((1, 0), Direction::Right);
+        row.teleporter(2, 5);
+        assert_eq!(row.convey(), vec![row.to(1, 5)]);
+    }
 }
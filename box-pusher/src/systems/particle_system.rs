@@ -0,0 +1,42 @@
+use specs::Entities;
+use specs::Join;
+use specs::System;
+use specs::Write;
+use specs::WriteStorage;
+
+use crate::components::Emitter;
+use crate::particles::Particles;
+
+pub struct ParticleSystem {
+    pub delta_time: f32,
+}
+
+impl<'s> System<'s> for ParticleSystem {
+    type SystemData = (
+        WriteStorage<'s, Emitter>,
+        Write<'s, Particles>,
+        Entities<'s>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut emitters, mut particles, entities) = data;
+
+        // spawn the burst, then keep up with the emitter's rate
+        for (emitter, entity) in (&mut emitters, &entities).join() {
+            emitter.time += self.delta_time;
+            let effect = emitter.effect;
+            let time = emitter.time.min(effect.duration);
+            let due = effect.burst + (time * effect.rate) as u32;
+            if due > emitter.spawned {
+                particles.emit(&effect, emitter.origin, due - emitter.spawned);
+                emitter.spawned = due;
+            }
+
+            if emitter.time >= effect.duration {
+                entities.delete(entity).expect("bad entity");
+            }
+        }
+
+        particles.update(self.delta_time);
+    }
+}
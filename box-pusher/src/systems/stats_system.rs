@@ -0,0 +1,35 @@
+use specs::Read;
+use specs::System;
+use specs::Write;
+
+use crate::resources::GameEvent;
+use crate::resources::GameEvents;
+use crate::resources::Messages;
+use crate::resources::Stats;
+
+/// Counts moves, pushes, bumps and undos for the current level. Loading
+/// a checkpoint counts as an undo, restarting starts the count over.
+pub struct StatsSystem {}
+
+impl<'s> System<'s> for StatsSystem {
+    type SystemData = (Write<'s, Stats>, Write<'s, Messages>, Read<'s, GameEvents>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut stats, mut messages, events) = data;
+
+        for event in events.iter() {
+            match event {
+                GameEvent::PlayerMoved { .. } => stats.moves += 1,
+                GameEvent::BoxPushed { .. } => stats.pushes += 1,
+                GameEvent::PushBlocked { .. } => stats.blocked += 1,
+                GameEvent::Undo | GameEvent::CheckpointLoaded { .. } => stats.undos += 1,
+                GameEvent::Restart => *stats = Stats::default(),
+                GameEvent::LevelComplete => messages.show(format!(
+                    "level complete in {} moves and {} pushes",
+                    stats.moves, stats.pushes
+                )),
+                _ => (),
+            }
+        }
+    }
+}
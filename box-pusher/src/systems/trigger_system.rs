@@ -0,0 +1,285 @@
+use specs::Entities;
+use specs::Join;
+use specs::ReadStorage;
+use specs::System;
+use specs::WriteStorage;
+use std::collections::HashSet;
+
+use crate::components::Door;
+use crate::components::Immovable;
+use crate::components::Movable;
+use crate::components::PlateMode;
+use crate::components::Player;
+use crate::components::Position;
+use crate::components::PressurePlate;
+use crate::components::Sprite;
+
+pub struct TriggerSystem {}
+
+impl<'s> System<'s> for TriggerSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        ReadStorage<'s, Position>,
+        ReadStorage<'s, Player>,
+        ReadStorage<'s, Movable>,
+        WriteStorage<'s, PressurePlate>,
+        WriteStorage<'s, Door>,
+        WriteStorage<'s, Immovable>,
+        WriteStorage<'s, Sprite>,
+        Entities<'s>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            positions,
+            players,
+            movables,
+            mut plates,
+            mut doors,
+            mut immovables,
+            mut sprites,
+            entities,
+        ) = data;
+
+        // find tiles that something is standing on
+        let occupied: HashSet<_> = (&positions, &players)
+            .join()
+            .map(|(pos, _)| (pos.x, pos.y))
+            .chain(
+                (&positions, &movables)
+                    .join()
+                    .map(|(pos, _)| (pos.x, pos.y)),
+            )
+            .collect();
+
+        // update plate states
+        for (pos, plate, spr) in (&positions, &mut plates, &mut sprites).join() {
+            let pressed = occupied.contains(&(pos.x, pos.y));
+
+            match plate.mode {
+                PlateMode::Hold => plate.active = pressed,
+                PlateMode::Toggle => {
+                    if pressed && !plate.pressed {
+                        plate.active = !plate.active;
+                    }
+                }
+            }
+
+            plate.pressed = pressed;
+            spr.part_pos = if pressed {
+                plate.down_part
+            } else {
+                plate.up_part
+            };
+        }
+
+        // any active linked plate flips a door from how it was authored
+        let flipped: HashSet<_> = (&plates)
+            .join()
+            .filter(|p| p.active)
+            .flat_map(|p| p.doors.iter().copied())
+            .collect();
+
+        // update door states
+        for (entity, pos, door, spr) in (&entities, &positions, &mut doors, &mut sprites).join() {
+            let open = door.default_open != flipped.contains(&entity);
+            if open == door.open {
+                continue;
+            }
+
+            // doors cannot close on top of something
+            if !open && occupied.contains(&(pos.x, pos.y)) {
+                continue;
+            }
+
+            door.open = open;
+            if open {
+                immovables.remove(entity);
+                spr.part_pos = door.open_part;
+            } else {
+                immovables.insert(entity, Immovable).expect("bad entity");
+                spr.part_pos = door.closed_part;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use duku::Vec2;
+    use specs::Builder;
+    use specs::Entity;
+    use specs::RunNow;
+    use specs::World as SpecsWorld;
+    use specs::WorldExt;
+
+    use super::*;
+    use crate::components::Direction;
+
+    // a plate on (0, 0) linked to a door on (1, 0)
+    struct Room {
+        world: SpecsWorld,
+        plate: Entity,
+        door: Entity,
+    }
+
+    impl Room {
+        fn new(mode: PlateMode, open: bool) -> Self {
+            let mut world = SpecsWorld::new();
+            world.register::<Position>();
+            world.register::<Player>();
+            world.register::<Movable>();
+            world.register::<PressurePlate>();
+            world.register::<Door>();
+            world.register::<Immovable>();
+            world.register::<Sprite>();
+
+            let door = world
+                .create_entity()
+                .with(position(1))
+                .with(sprite())
+                .with(Door {
+                    open,
+                    default_open: open,
+                    closed_part: Vec2::new(0.0, 0.0),
+                    open_part: Vec2::new(16.0, 0.0),
+                })
+                .build();
+            if !open {
+                world
+                    .write_storage()
+                    .insert(door, Immovable)
+                    .expect("bad entity");
+            }
+            let plate = world
+                .create_entity()
+                .with(position(0))
+                .with(sprite())
+                .with(PressurePlate {
+                    mode,
+                    doors: vec![door],
+                    pressed: false,
+                    active: false,
+                    up_part: Vec2::new(0.0, 0.0),
+                    down_part: Vec2::new(16.0, 0.0),
+                })
+                .build();
+
+            Self { world, plate, door }
+        }
+
+        fn add_box(&mut self, x: i32) -> Entity {
+            self.world
+                .create_entity()
+                .with(position(x))
+                .with(Movable)
+                .build()
+        }
+
+        fn move_to(&mut self, entity: Entity, x: i32) {
+            let mut positions = self.world.write_storage::<Position>();
+            positions.get_mut(entity).expect("bad entity").x = x;
+        }
+
+        fn run(&mut self) {
+            TriggerSystem {}.run_now(&self.world);
+            self.world.maintain();
+        }
+
+        fn active(&self) -> bool {
+            self.world
+                .read_storage::<PressurePlate>()
+                .get(self.plate)
+                .expect("bad entity")
+                .active
+        }
+
+        // open, and if the door can be walked through
+        fn door(&self) -> (bool, bool) {
+            let open = self
+                .world
+                .read_storage::<Door>()
+                .get(self.door)
+                .expect("bad entity")
+                .open;
+            let passable = !self.world.read_storage::<Immovable>().contains(self.door);
+            (open, passable)
+        }
+    }
+
+    fn position(x: i32) -> Position {
+        Position {
+            x,
+            y: 0,
+            offset: Vec2::default(),
+            direction: Direction::Down,
+        }
+    }
+
+    fn sprite() -> Sprite {
+        Sprite {
+            texture: "plate.png".to_string(),
+            part_pos: Vec2::default(),
+            part_size: Vec2::new(16.0, 16.0),
+        }
+    }
+
+    #[test]
+    fn hold_plate() {
+        let mut room = Room::new(PlateMode::Hold, false);
+        let weight = room.add_box(0);
+        room.run();
+        assert!(room.active());
+        assert_eq!(room.door(), (true, true));
+
+        room.move_to(weight, -1);
+        room.run();
+        assert!(!room.active());
+        assert_eq!(room.door(), (false, false));
+    }
+
+    #[test]
+    fn toggle_plate() {
+        let mut room = Room::new(PlateMode::Toggle, false);
+        let weight = room.add_box(0);
+        room.run();
+        assert!(room.active());
+
+        // stepping off keeps it on, the next step turns it off
+        room.move_to(weight, -1);
+        room.run();
+        room.run();
+        assert!(room.active());
+        assert_eq!(room.door(), (true, true));
+
+        room.move_to(weight, 0);
+        room.run();
+        assert!(!room.active());
+        assert_eq!(room.door(), (false, false));
+    }
+
+    #[test]
+    fn plate_closes_an_open_door() {
+        let mut room = Room::new(PlateMode::Hold, true);
+        let weight = room.add_box(0);
+        room.run();
+        assert_eq!(room.door(), (false, false));
+
+        room.move_to(weight, -1);
+        room.run();
+        assert_eq!(room.door(), (true, true));
+    }
+
+    #[test]
+    fn door_stays_open_while_occupied() {
+        let mut room = Room::new(PlateMode::Hold, true);
+        room.add_box(0);
+        let blocker = room.add_box(1);
+        room.run();
+        assert_eq!(room.door(), (true, true));
+
+        room.move_to(blocker, 2);
+        room.run();
+        assert_eq!(room.door(), (false, false));
+    }
+}
@@ -0,0 +1,55 @@
+use specs::Read;
+use specs::System;
+use specs::Write;
+use specs::WriteStorage;
+
+use crate::components::Position;
+use crate::components::PressurePlate;
+use crate::components::Sprite;
+use crate::components::Warp;
+use crate::resources::Button;
+use crate::resources::GameEvent;
+use crate::resources::GameEvents;
+use crate::resources::History;
+use crate::resources::Inputs;
+use crate::resources::Progress;
+
+pub struct UndoSystem {}
+
+impl<'s> System<'s> for UndoSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        WriteStorage<'s, Position>,
+        WriteStorage<'s, PressurePlate>,
+        WriteStorage<'s, Sprite>,
+        WriteStorage<'s, Warp>,
+        Write<'s, History>,
+        Read<'s, Inputs>,
+        Write<'s, GameEvents>,
+        Write<'s, Progress>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            mut positions,
+            mut plates,
+            mut sprites,
+            mut warps,
+            mut history,
+            inputs,
+            mut events,
+            mut progress,
+        ) = data;
+
+        if !inputs.keys_typed.contains(&Button::Undo) {
+            return;
+        }
+
+        // restore the board from before the last turn
+        if let Some(snapshot) = history.turns.pop() {
+            snapshot.restore(&mut positions, &mut plates, &mut sprites, &mut warps);
+            progress.checked = false;
+            events.send(GameEvent::Undo);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::capture;
+use crate::capture::Recorder;
+use crate::components::Direction;
+use crate::config::Command;
+use crate::config::Config;
+use crate::crt::CrtSettings;
+use crate::error::Result;
+use crate::generator;
+use crate::generator::Params;
+use crate::level;
+use crate::render::Image;
+use crate::render::SoftwareRenderer;
+use crate::replay::Replay;
+use crate::resources::Button;
+use crate::solver;
+use crate::solver::Outcome;
+use crate::solver::Puzzle;
+use crate::systems::DrawSystem;
+use crate::validate;
+use crate::world::World;
+use crate::xsb;
+
+// renders step at a steady 60 frames a second
+const FRAME_TIME: f32 = 1.0 / 60.0;
+
+// gives up on moves that never finish, like walking onto a conveyor loop
+const MAX_MOVE_FRAMES: u32 = 600;
+
+struct RenderOptions<'a> {
+    frames: u32,
+    moves: Option<&'a str>,
+    out: &'a Path,
+    gif: Option<&'a Path>,
+    scale: u32,
+    crt: Option<&'a CrtSettings>,
+    compare: Option<&'a Path>,
+}
+
+/// Runs a headless subcommand. Returns if it succeeded.
+pub fn run(command: &Command) -> Result<bool> {
+    match command {
+        Command::Validate { file, budget } => validate::check_file(file, *budget),
+        Command::Solve {
+            file,
+            level,
+            budget,
+        } => solve(file, level.as_deref(), *budget),
+        Command::Generate { params, out } => generate(params, out.as_deref()),
+        Command::Render {
+            file,
+            level,
+            frames,
+            moves,
+            out,
+            gif,
+            scale,
+            crt,
+            compare,
+        } => render(
+            file,
+            level.as_deref(),
+            &RenderOptions {
+                frames: *frames,
+                moves: moves.as_deref(),
+                out,
+                gif: gif.as_deref(),
+                scale: *scale,
+                crt: crt.as_ref(),
+                compare: compare.as_deref(),
+            },
+        ),
+        Command::Play | Command::Help => Ok(true),
+    }
+}
+
+fn solve(file: &Path, name: Option<&str>, budget: Duration) -> Result<bool> {
+    let levels = level::parse_file(file)?;
+    let level = match name {
+        Some(name) => levels.iter().find(|l| l.name == name),
+        None => levels.first(),
+    }
+    .ok_or("level not found")?;
+
+    let puzzle = Puzzle::from_level(level)?;
+    match solver::solve(&puzzle, budget) {
+        Outcome::Solved(solution) => {
+            let pushes = solution.moves.iter().filter(|m| m.push).count();
+            println!("{}", xsb::write_moves(&solution.moves));
+            println!(
+                "{}: {} moves, {} pushes, {} states searched",
+                level.name,
+                solution.moves.len(),
+                pushes,
+                solution.states
+            );
+            Ok(true)
+        }
+        Outcome::Unsolvable => {
+            println!("{}: cannot be solved", level.name);
+            Ok(false)
+        }
+        Outcome::GaveUp => {
+            println!(
+                "{}: not solved within {} seconds",
+                level.name,
+                budget.as_secs_f32()
+            );
+            Ok(false)
+        }
+    }
+}
+
+fn generate(params: &Params, out: Option<&Path>) -> Result<bool> {
+    let level = generator::generate(params)?;
+
+    match out {
+        Some(path) if path.extension().and_then(|e| e.to_str()) == Some("ldtk") => {
+            level::save(path, std::slice::from_ref(&level))?
+        }
+        Some(path) => fs::write(path, xsb::write(&level)?)?,
+        None => print!("{}", xsb::write(&level)?),
+    }
+
+    Ok(true)
+}
+
+/// Draws a level without a GPU, optionally checking it against a golden
+/// image. Animations run for `frames` steps first, then the player follows
+/// `moves`, waiting for every move to finish before the next.
+fn render(file: &Path, name: Option<&str>, options: &RenderOptions<'_>) -> Result<bool> {
+    // replays bring their own level and seed
+    let replay = match file.extension().and_then(|e| e.to_str()) {
+        Some("replay") => Some(Replay::load(file)?),
+        _ => None,
+    };
+    let (file, name, seed) = match &replay {
+        Some(replay) => (
+            replay.level_file.as_path(),
+            Some(replay.level.as_str()),
+            Some(replay.seed),
+        ),
+        None => (file, name, None),
+    };
+
+    let levels = level::parse_file(file)?;
+    let level = match name {
+        Some(name) => levels.iter().find(|l| l.name == name),
+        None => levels.first(),
+    }
+    .ok_or("level not found")?;
+    let moves = match options.moves {
+        Some(moves) => xsb::parse_moves(moves)?,
+        None => vec![],
+    };
+
+    let config = Config {
+        mute: true,
+        seed,
+        ..Default::default()
+    };
+    let mut world = World::new(&config)?;
+    world.load_music("assets/music.json")?;
+    level::spawn(&mut world, level)?;
+
+    let mut renderer = SoftwareRenderer::new(config.view_width(), config.view_height(), "assets")?;
+    let mut recorder = options.gif.map(|_| Recorder::new(None));
+    let mut step = |world: &mut World, paused: bool| -> Result<()> {
+        tick(world, paused);
+        if let Some(recorder) = &mut recorder {
+            draw(world, &mut renderer, &config);
+            recorder.add(renderer.image(), FRAME_TIME);
+        }
+        Ok(())
+    };
+
+    for _ in 0..options.frames {
+        step(&mut world, false)?;
+    }
+    for tick in replay.iter().flat_map(|r| r.ticks()) {
+        if !tick.paused() {
+            world.set_buttons(tick.buttons());
+        }
+        step(&mut world, tick.paused())?;
+    }
+    for direction in moves {
+        world.set_buttons(vec![button(direction)].into_iter().collect());
+        step(&mut world, false)?;
+        world.set_buttons(HashSet::new());
+        for _ in 0..MAX_MOVE_FRAMES {
+            if world.is_settled() {
+                break;
+            }
+            step(&mut world, false)?;
+        }
+    }
+
+    draw(&world, &mut renderer, &config);
+    capture::finish(renderer.image(), options.scale, options.crt).save_png(options.out)?;
+    if let (Some(recorder), Some(path)) = (&recorder, options.gif) {
+        recorder.save_gif(path, options.scale, options.crt)?;
+    }
+
+    let path = match options.compare {
+        Some(path) => path,
+        None => return Ok(true),
+    };
+    match renderer.image().diff(&Image::load_png(path)?, 0) {
+        Some(0) => {
+            println!("{}: matches {}", level.name, path.display());
+            Ok(true)
+        }
+        Some(count) => {
+            println!(
+                "{}: {} pixels differ from {}",
+                level.name,
+                count,
+                path.display()
+            );
+            Ok(false)
+        }
+        None => {
+            println!("{}: size differs from {}", level.name, path.display());
+            Ok(false)
+        }
+    }
+}
+
+/// Runs one frame of gameplay, the same way the game does.
+pub fn tick(world: &mut World, paused: bool) {
+    world.step(paused, FRAME_TIME);
+
+    // there is no screen, so new messages go to the console
+    for message in world.messages().iter().filter(|m| m.age <= FRAME_TIME) {
+        println!("{}", message.text);
+    }
+}
+
+fn draw(world: &World, renderer: &mut SoftwareRenderer, config: &Config) {
+    renderer.clear();
+    world.run_system(DrawSystem {
+        renderer,
+        view_width: config.view_width(),
+        view_height: config.view_height(),
+        tile_size: config.tile_size,
+        overlay: None,
+    });
+}
+
+fn button(direction: Direction) -> Button {
+    match direction {
+        Direction::Up => Button::Up,
+        Direction::Down => Button::Down,
+        Direction::Left => Button::Left,
+        Direction::Right => Button::Right,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::ReplayWriter;
+    use crate::replay::Tick;
+    use std::env;
+
+    #[test]
+    fn golden_image() {
+        let level = level::parse_file("tests/golden/small.xsb")
+            .unwrap()
+            .remove(0);
+        let config = Config {
+            mute: true,
+            ..Default::default()
+        };
+        let mut world = World::new(&config).unwrap();
+        level::spawn(&mut world, &level).unwrap();
+        tick(&mut world, false);
+
+        let mut renderer =
+            SoftwareRenderer::new(config.view_width(), config.view_height(), "assets").unwrap();
+        draw(&world, &mut renderer, &config);
+        let golden = Image::load_png("tests/golden/small.png").unwrap();
+        assert_eq!(renderer.image().diff(&golden, 0), Some(0));
+    }
+
+    #[test]
+    fn play_replay() {
+        let dir = env::temp_dir();
+        let level_file = dir.join("box-pusher-play.xsb");
+        let path = dir.join("box-pusher-play.replay");
+        fs::write(&level_file, "######\n#@$ .#\n######\n").unwrap();
+
+        // two pushes to the right, letting each one finish sliding
+        let right: HashSet<_> = vec![Button::Right].into_iter().collect();
+        let mut writer = ReplayWriter::create(&path, &level_file, "Level1", 7).unwrap();
+        for _ in 0..2 {
+            writer.push(Tick::new(&right, false)).unwrap();
+            for _ in 0..30 {
+                writer.push(Tick::default()).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+
+        let replay = Replay::load(&path).unwrap();
+        let level = level::parse_file(&replay.level_file).unwrap().remove(0);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&level_file).unwrap();
+        let config = Config {
+            mute: true,
+            seed: Some(replay.seed),
+            ..Default::default()
+        };
+        let mut world = World::new(&config).unwrap();
+        level::spawn(&mut world, &level).unwrap();
+        let start = world.boards().remove(0);
+
+        // the same way render plays them back
+        for tick in replay.ticks() {
+            if !tick.paused() {
+                world.set_buttons(tick.buttons());
+            }
+            super::tick(&mut world, tick.paused());
+        }
+
+        let (x, y) = start.player;
+        let board = world.boards().remove(0);
+        assert_eq!(board.player, (x + 2, y));
+        assert_eq!(board.boxes, vec![((x + 3, y), None)]);
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::components::Direction;
+use crate::error::Result;
+use crate::level;
+use crate::level::InstanceKind;
+use crate::level::Level;
+use crate::level::TileLayer;
+use crate::solver;
+use crate::solver::Outcome;
+use crate::solver::Puzzle;
+
+type Tile = (i32, i32);
+
+/// Checks every level in an LDtk or XSB file and prints what is wrong
+/// with them. Levels that cannot be read count as failed, the rest are
+/// still checked. Returns if all of them passed.
+pub fn check_file(path: impl AsRef<Path>, budget: Duration) -> Result<bool> {
+    let levels = level::parse_each(path)?;
+
+    let mut failed = 0;
+    for (name, level) in &levels {
+        let level = match level {
+            Ok(level) => level,
+            Err(err) => {
+                println!("{}: {}", name, err);
+                failed += 1;
+                continue;
+            }
+        };
+        let mut problems = validate(level);
+
+        // only bother solving levels that look right
+        if problems.is_empty() {
+            match check_solvable(level, budget) {
+                Solvability::Solved => (),
+                Solvability::Unchecked(reason) => println!(
+                    "{}: warning: solvability not checked (unsupported mechanics, {})",
+                    name, reason
+                ),
+                Solvability::Failed(problem) => problems.push(problem),
+            }
+        }
+
+        for problem in &problems {
+            println!("{}: {}", name, problem);
+        }
+        if !problems.is_empty() {
+            failed += 1;
+        }
+    }
+
+    println!(
+        "{} of {} levels passed",
+        levels.len() - failed,
+        levels.len()
+    );
+    Ok(failed == 0)
+}
+
+/// Checks that a level can be played, returning everything wrong with it.
+pub fn validate(level: &Level) -> Vec<String> {
+    let mut problems = vec![];
+
+    for unknown in &level.unknown {
+        problems.push(format!("unknown {}", unknown));
+    }
+
+    // exactly one player
+    let players: Vec<_> = level
+        .instances
+        .iter()
+        .filter(|i| matches!(i.kind, InstanceKind::Player))
+        .collect();
+    match players.len() {
+        0 => problems.push("no player".to_string()),
+        1 => (),
+        n => problems.push(format!("{} players", n)),
+    }
+
+    // every box needs a goal of its color
+    let mut boxes = HashMap::new();
+    let mut goals = HashMap::new();
+    for instance in &level.instances {
+        match instance.kind {
+            InstanceKind::Box(color) => *boxes.entry(color).or_insert(0) += 1,
+            InstanceKind::Goal(color) => *goals.entry(color).or_insert(0) += 1,
+            _ => (),
+        }
+    }
+    let mut colors: Vec<_> = boxes.keys().chain(goals.keys()).copied().collect();
+    colors.sort();
+    colors.dedup();
+    for color in colors {
+        let box_count = boxes.get(&color).copied().unwrap_or(0);
+        let goal_count = goals.get(&color).copied().unwrap_or(0);
+        if box_count != goal_count {
+            let name = color.map(|c| format!("{:?} ", c)).unwrap_or_default();
+            problems.push(format!(
+                "{} {}boxes but {} {}goals",
+                box_count, name, goal_count, name
+            ));
+        }
+    }
+
+    // boxes and goals need to be reachable by the player
+    if let Some(player) = players.first() {
+        let reach = reachable(level, (player.x, player.y));
+        for instance in &level.instances {
+            let name = match instance.kind {
+                InstanceKind::Box(_) => "box",
+                InstanceKind::Goal(_) => "goal",
+                _ => continue,
+            };
+            if !reach.contains(&(instance.x, instance.y)) {
+                problems.push(format!(
+                    "{} at {}, {} cannot be reached",
+                    name, instance.x, instance.y
+                ));
+            }
+        }
+
+        // the player should not be able to walk off the level
+        let floor = floor(level);
+        let walls = walls(level);
+        let mut leaks: Vec<_> = reach
+            .iter()
+            .filter(|(x, y)| {
+                Direction::ALL.iter().any(|d| {
+                    let (xo, yo) = d.offset();
+                    let next = (x + xo, y + yo);
+                    !floor.contains(&next) && !walls.contains(&next)
+                })
+            })
+            .collect();
+        leaks.sort();
+        for (x, y) in leaks {
+            problems.push(format!("floor at {}, {} is not enclosed", x, y));
+        }
+    }
+
+    // boxes that can never reach a goal
+    if let Ok(puzzle) = Puzzle::from_level(level) {
+        let live = puzzle.live_squares();
+        for ((x, y), color) in &puzzle.boxes {
+            // missing goals are already reported
+            if !puzzle.goals.values().any(|c| c == color) {
+                continue;
+            }
+            if !live
+                .get(color)
+                .map(|l| l.contains(&(*x, *y)))
+                .unwrap_or(false)
+            {
+                problems.push(format!("box at {}, {} is on a dead square", x, y));
+            }
+        }
+    }
+
+    problems
+}
+
+/// What the solver made of a level.
+pub enum Solvability {
+    Solved,
+    // the level uses things the solver does not know about
+    Unchecked(String),
+    Failed(String),
+}
+
+/// Runs the solver on a level, returning why it could not be solved.
+pub fn check_solvable(level: &Level, budget: Duration) -> Solvability {
+    let puzzle = match Puzzle::from_level(level) {
+        Ok(puzzle) => puzzle,
+        Err(err) => return Solvability::Unchecked(err.to_string()),
+    };
+
+    match solver::solve(&puzzle, budget) {
+        Outcome::Solved(_) => Solvability::Solved,
+        Outcome::Unsolvable => Solvability::Failed("cannot be solved".to_string()),
+        Outcome::GaveUp => Solvability::Failed(format!(
+            "not solved within {} seconds",
+            budget.as_secs_f32()
+        )),
+    }
+}
+
+/// Tiles the player could walk to if nothing was in the way.
+/// Linked teleporters count as connected.
+pub fn reachable(level: &Level, from: Tile) -> HashSet<Tile> {
+    let floor = floor(level);
+
+    let tiles: HashMap<_, _> = level
+        .instances
+        .iter()
+        .filter_map(|i| i.iid.as_deref().map(|iid| (iid, (i.x, i.y))))
+        .collect();
+    let mut links: HashMap<Tile, Vec<Tile>> = HashMap::new();
+    for instance in &level.instances {
+        if let InstanceKind::Teleporter {
+            target: Some(target),
+        } = &instance.kind
+        {
+            if let Some(tile) = tiles.get(target.as_str()) {
+                let here = (instance.x, instance.y);
+                links.entry(here).or_default().push(*tile);
+                links.entry(*tile).or_default().push(here);
+            }
+        }
+    }
+
+    let mut reach = HashSet::new();
+    let mut queue = VecDeque::new();
+    reach.insert(from);
+    queue.push_back(from);
+
+    while let Some((x, y)) = queue.pop_front() {
+        let neighbours = Direction::ALL
+            .iter()
+            .map(|d| d.offset())
+            .map(|(xo, yo)| (x + xo, y + yo))
+            .chain(links.get(&(x, y)).into_iter().flatten().copied());
+
+        for next in neighbours.collect::<Vec<_>>() {
+            if floor.contains(&next) && reach.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    reach
+}
+
+fn walls(level: &Level) -> HashSet<Tile> {
+    level
+        .tiles
+        .iter()
+        .filter(|t| t.layer == TileLayer::Collisions)
+        .map(|t| (t.x, t.y))
+        .collect()
+}
+
+/// Background tiles that have no wall on them.
+pub fn floor(level: &Level) -> HashSet<Tile> {
+    let mut floor: HashSet<_> = level
+        .tiles
+        .iter()
+        .filter(|t| t.layer == TileLayer::Background)
+        .map(|t| (t.x, t.y))
+        .collect();
+    for tile in level
+        .tiles
+        .iter()
+        .filter(|t| t.layer == TileLayer::Collisions)
+    {
+        floor.remove(&(tile.x, tile.y));
+    }
+    floor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xsb;
+
+    fn level() -> Level {
+        xsb::read_each("#####\n#@$.#\n#####\n").remove(0).1.unwrap()
+    }
+
+    #[test]
+    fn solvable() {
+        let level = level();
+        assert!(validate(&level).is_empty());
+        let check = check_solvable(&level, Duration::from_secs(1));
+        assert!(matches!(check, Solvability::Solved));
+    }
+
+    #[test]
+    fn unsupported_mechanics_are_unchecked() {
+        let mut level = level();
+        level.rules.pull = true;
+        let check = check_solvable(&level, Duration::from_secs(1));
+        assert!(matches!(check, Solvability::Unchecked(_)));
+    }
+}
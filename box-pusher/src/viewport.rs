@@ -0,0 +1,87 @@
+use duku::Vec2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Largest whole number scale, with black bars around it.
+    Integer,
+    /// Largest scale that keeps the aspect ratio.
+    Fit,
+    /// Fill the whole window.
+    Stretch,
+}
+
+impl ScaleMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Integer => Self::Fit,
+            Self::Fit => Self::Stretch,
+            Self::Stretch => Self::Integer,
+        }
+    }
+}
+
+/// Where the game's view ends up inside the window.
+#[derive(Debug, Copy, Clone)]
+pub struct Viewport {
+    pub mode: ScaleMode,
+    pub view: (u32, u32),
+    pub window: (u32, u32),
+    pub tile_size: u32,
+}
+
+impl Viewport {
+    pub fn new(mode: ScaleMode, view: (u32, u32), window: (u32, u32), tile_size: u32) -> Self {
+        Self {
+            mode,
+            view,
+            window,
+            tile_size,
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.window = (width.max(1), height.max(1));
+    }
+
+    /// Position and size of the view in window pixels, from the top left.
+    pub fn rect(&self) -> (Vec2, Vec2) {
+        let window = Vec2::new(self.window.0 as f32, self.window.1 as f32);
+        let view = Vec2::new(self.view.0 as f32, self.view.1 as f32);
+        let fit = (window.x / view.x).min(window.y / view.y);
+
+        let size = match self.mode {
+            ScaleMode::Integer => view * fit.floor().max(1.0),
+            ScaleMode::Fit => view * fit,
+            ScaleMode::Stretch => window,
+        };
+
+        ((window - size) * 0.5, size)
+    }
+
+    /// Converts window pixels into view pixels, measured from the bottom
+    /// left like the level. Returns `None` outside of the view.
+    pub fn screen_to_view(&self, pos: Vec2) -> Option<Vec2> {
+        let (offset, size) = self.rect();
+        let local = pos - offset;
+        if local.x < 0.0 || local.y < 0.0 || local.x >= size.x || local.y >= size.y {
+            return None;
+        }
+
+        Some(Vec2::new(
+            local.x / size.x * self.view.0 as f32,
+            (1.0 - local.y / size.y) * self.view.1 as f32,
+        ))
+    }
+
+    /// Converts window pixels into the tile under them.
+    pub fn screen_to_grid(&self, pos: Vec2) -> Option<(i32, i32)> {
+        let view = self.screen_to_view(pos)?;
+        let tile = self.tile_size as f32;
+
+        // tiles hang down from their y coordinate
+        Some((
+            (view.x / tile).floor() as i32,
+            (view.y / tile).floor() as i32 + 1,
+        ))
+    }
+}
@@ -1,41 +1,86 @@
-use duku::Duku;
-use duku::Handle;
-use duku::Texture;
 use duku::Vec2;
-use kira::manager::AudioManager;
-use kira::sound::SoundId;
 use specs::Builder;
+use specs::Entity;
+use specs::Join;
 use specs::RunNow;
 use specs::System;
 use specs::World as SpecsWorld;
 use specs::WorldExt;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::path::Path;
-use std::time::Instant;
 
 use super::Result;
+use crate::audio;
+use crate::audio::Audio;
+use crate::audio::Volume;
 use crate::components::Animation;
 use crate::components::Animations;
+use crate::components::BoxColor;
+use crate::components::Color;
+use crate::components::Conveyor;
 use crate::components::Direction;
+use crate::components::Door;
+use crate::components::Emitter;
+use crate::components::Goal;
+use crate::components::GoalColor;
 use crate::components::Immovable;
+use crate::components::Layer;
 use crate::components::Movable;
+use crate::components::OneWay;
+use crate::components::PlateMode;
 use crate::components::Player;
 use crate::components::Position;
+use crate::components::PressurePlate;
 use crate::components::Sprite;
+use crate::components::Teleporter;
+use crate::components::Warp;
+use crate::config::Config;
+use crate::level;
+use crate::particles::Particles;
+use crate::render;
+use crate::resources::Achievements;
+use crate::resources::Button;
+use crate::resources::DrawOrder;
+use crate::resources::GameEvents;
+use crate::resources::HintMarks;
+use crate::resources::History;
 use crate::resources::Inputs;
+use crate::resources::Message;
+use crate::resources::Messages;
+use crate::resources::Progress;
+use crate::resources::Rules;
+use crate::resources::Snapshot;
+use crate::resources::Stats;
+use crate::solver::Board;
+use crate::solver::Tile;
+use crate::systems::AchievementSystem;
+use crate::systems::AnimateSystem;
+use crate::systems::CheckpointSystem;
+use crate::systems::EffectSystem;
+use crate::systems::GoalSystem;
+use crate::systems::HintSystem;
+use crate::systems::MessageSystem;
+use crate::systems::MoveSystem;
+use crate::systems::ParticleSystem;
+use crate::systems::StatsSystem;
+use crate::systems::TriggerSystem;
+use crate::systems::UndoSystem;
 
 pub struct World {
     specs: SpecsWorld,
-    audio: AudioManager,
-    sprites: HashMap<String, Handle<Texture>>,
-    sounds: HashMap<String, SoundId>,
-    sound_start: Instant,
-    sound_cooldown: f64,
+    audio: Box<dyn Audio>,
+    volume: Volume,
+    muted: bool,
+    layers: Vec<String>,
+    // seeds everything random in gameplay, so replays come out the same
+    seed: u64,
 }
 
 impl World {
-    pub fn new() -> Result<Self> {
+    pub fn new(config: &Config) -> Result<Self> {
         let mut specs = SpecsWorld::new();
+        let seed = config.seed.unwrap_or(0);
 
         // register components
         specs.register::<Sprite>();
@@ -45,22 +90,40 @@ impl World {
         specs.register::<Animations>();
         specs.register::<Movable>();
         specs.register::<Immovable>();
+        specs.register::<PressurePlate>();
+        specs.register::<Door>();
+        specs.register::<Teleporter>();
+        specs.register::<Warp>();
+        specs.register::<Goal>();
+        specs.register::<BoxColor>();
+        specs.register::<GoalColor>();
+        specs.register::<OneWay>();
+        specs.register::<Conveyor>();
+        specs.register::<Layer>();
+        specs.register::<Emitter>();
 
         // insert resources
         specs.insert(Inputs::default());
+        specs.insert(Rules::default());
+        specs.insert(Progress::default());
+        specs.insert(History::default());
+        specs.insert(DrawOrder::default());
+        specs.insert(Particles::new(seed));
+        specs.insert(GameEvents::default());
+        specs.insert(Stats::default());
+        specs.insert(Achievements::default());
+        specs.insert(Messages::default());
+        specs.insert(HintMarks::default());
 
-        let audio = AudioManager::new(Default::default()).expect("bad kira");
-
-        let sprites = HashMap::new();
-        let sounds = HashMap::new();
+        let audio = audio::open(config.mute, config.audio_log.as_deref())?;
 
         Ok(Self {
-            sound_start: Instant::now(),
-            sound_cooldown: 0.0,
             specs,
             audio,
-            sprites,
-            sounds,
+            volume: Volume::default(),
+            muted: false,
+            layers: level::default_layers(),
+            seed,
         })
     }
 
@@ -68,59 +131,236 @@ impl World {
         system.run_now(&self.specs);
     }
 
-    pub fn add_sprite(&mut self, duku: &mut Duku, path: impl AsRef<Path>) -> Result<()> {
-        let p = path.as_ref();
-        let name = p
-            .file_name()
-            .map(|n| n.to_str().unwrap_or(""))
-            .unwrap_or("");
-        let sprite = duku.create_texture_png(p, None)?;
-        self.sprites.insert(name.to_string(), sprite);
+    /// Loads the sound effects and their event mapping.
+    pub fn load_sounds(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.audio.load_sounds(path.as_ref())
+    }
+
+    pub fn play_sound(&mut self, name: &str) -> Result<()> {
+        self.audio.play_sound(name)
+    }
+
+    /// Loads the music tracks levels can ask for.
+    pub fn load_music(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.audio.load_music(path.as_ref())
+    }
+
+    pub fn play_music(&mut self, name: Option<&str>) -> Result<()> {
+        self.audio.play_music(name)
+    }
+
+    /// Muffles the music while a menu is open.
+    pub fn set_music_paused(&mut self, paused: bool) {
+        self.audio.set_paused(paused);
+    }
+
+    pub fn set_volume(&mut self, volume: Volume) -> Result<()> {
+        self.volume = volume;
+        self.audio.set_volume(self.volume, self.muted)
+    }
+
+    /// Silences everything without touching the volume settings.
+    pub fn set_muted(&mut self, muted: bool) -> Result<()> {
+        self.muted = muted;
+        self.audio.set_volume(self.volume, self.muted)
+    }
+
+    pub fn update_audio(&mut self) -> Result<()> {
+        self.audio.update()
+    }
+
+    #[cfg(test)]
+    pub fn audio_events(&self) -> &[audio::AudioEvent] {
+        self.audio.recorded()
+    }
+
+    pub fn clear(&mut self) {
+        self.specs.delete_all();
+        self.specs.maintain();
+        self.specs.insert(History::default());
+        self.specs.insert(Progress::default());
+        self.specs.insert(Particles::new(self.seed));
+        self.specs.insert(GameEvents::default());
+        self.specs.insert(Stats::default());
+        self.specs.insert(HintMarks::default());
+    }
+
+    /// Plays sounds for this frame's gameplay events.
+    pub fn play_event_sounds(&mut self) -> Result<()> {
+        let names: Vec<_> = self
+            .specs
+            .read_resource::<GameEvents>()
+            .iter()
+            .filter_map(|event| self.audio.event_sound(event.name()))
+            .map(|name| name.to_string())
+            .collect();
+
+        for name in names {
+            self.play_sound(&name)?;
+        }
         Ok(())
     }
 
-    pub fn add_sound(&mut self, path: impl AsRef<Path>) {
-        let p = path.as_ref();
-        let name = p
-            .file_name()
-            .map(|n| n.to_str().unwrap_or(""))
-            .unwrap_or("");
-        let sound = self
-            .audio
-            .load_sound(p, Default::default())
-            .expect("bad sound");
-        self.sounds.insert(name.to_string(), sound);
-    }
-
-    pub fn play_sound(&mut self, name: &str) {
-        if self.sound_start.elapsed().as_secs_f64() > self.sound_cooldown {
-            let sound = self
-                .sounds
-                .get(name)
-                .expect("bad oliver, you did bad sound");
-
-            // set cooldown
-            self.sound_start = Instant::now();
-            self.sound_cooldown = sound.duration();
-
-            self.audio
-                .play(*sound, Default::default())
-                .expect("cannot play");
+    /// Runs one frame of gameplay and everything that reacts to it. The
+    /// game, replays and headless renders all go through here.
+    pub fn step(&mut self, paused: bool, delta_time: f32) {
+        if !paused {
+            self.run_system(UndoSystem {});
+            self.run_system(CheckpointSystem {});
+            self.run_system(MoveSystem {});
+            self.run_system(TriggerSystem {});
+            self.run_system(GoalSystem {});
+        }
+
+        // react to what happened in gameplay
+        self.run_system(EffectSystem {});
+        self.run_system(StatsSystem {});
+        self.run_system(AchievementSystem {});
+        self.run_system(HintSystem {});
+        if let Err(err) = self.play_event_sounds() {
+            println!("{}", err);
         }
+        self.clear_events();
+
+        self.run_system(AnimateSystem { delta_time });
+        self.run_system(ParticleSystem { delta_time });
+        self.run_system(MessageSystem { delta_time });
+        self.maintain();
+    }
+
+    /// Drops this frame's gameplay events once everything has seen them.
+    pub fn clear_events(&mut self) {
+        self.specs.write_resource::<GameEvents>().events.clear();
+    }
+
+    /// Removes entities that systems deleted this frame.
+    pub fn maintain(&mut self) {
+        self.specs.maintain();
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn buttons(&self) -> HashSet<Button> {
+        self.specs.read_resource::<Inputs>().keys_pressed.clone()
+    }
+
+    pub fn is_typed(&self, button: Button) -> bool {
+        self.specs
+            .read_resource::<Inputs>()
+            .keys_typed
+            .contains(&button)
+    }
+
+    /// Holds buttons down for the next frame, in place of the input system.
+    pub fn set_buttons(&mut self, pressed: HashSet<Button>) {
+        let mut inputs = self.specs.write_resource::<Inputs>();
+        inputs.keys_typed = pressed.difference(&inputs.keys_pressed).copied().collect();
+        inputs.keys_pressed = pressed;
+    }
+
+    /// The board now, then before each turn in the undo history, newest
+    /// first.
+    pub fn boards(&self) -> Vec<Board> {
+        let positions = self.specs.read_storage::<Position>();
+        let players = self.specs.read_storage::<Player>();
+        let movables = self.specs.read_storage::<Movable>();
+        let box_colors = self.specs.read_storage::<BoxColor>();
+        let history = self.specs.read_resource::<History>();
+        let entities = self.specs.entities();
+
+        let boxes: Vec<_> = (&entities, &movables)
+            .join()
+            .map(|(e, _)| (e, box_colors.get(e).map(|c| c.0)))
+            .collect();
+        let player = match (&entities, &players).join().next() {
+            Some((player, _)) => player,
+            None => return vec![],
+        };
+        let board = |tiles: &HashMap<Entity, Tile>| {
+            let mut board = Board {
+                boxes: boxes
+                    .iter()
+                    .filter_map(|(e, color)| tiles.get(e).map(|t| (*t, *color)))
+                    .collect(),
+                player: *tiles.get(&player)?,
+            };
+            board.boxes.sort();
+            Some(board)
+        };
+
+        let now = (&entities, &positions)
+            .join()
+            .map(|(e, p)| (e, (p.x, p.y)))
+            .collect();
+        let before = history.turns.iter().rev().map(|turn| {
+            turn.positions
+                .iter()
+                .map(|(e, x, y, _)| (*e, (*x, *y)))
+                .collect()
+        });
+        std::iter::once(now)
+            .chain(before)
+            .filter_map(|tiles| board(&tiles))
+            .collect()
+    }
+
+    /// Remembers the board as it is now as the start of the level.
+    pub fn save_start(&mut self) {
+        let start = Snapshot::take(
+            &self.specs.read_storage(),
+            &self.specs.read_storage(),
+            &self.specs.read_storage(),
+            &self.specs.read_storage(),
+            &self.specs.entities(),
+        );
+        self.specs.write_resource::<History>().start = Some(start);
+    }
+
+    /// Shows the walk to the next push and the box to push.
+    pub fn show_hint(&mut self, path: Vec<Tile>, push: Option<(Tile, Direction)>) {
+        self.specs.insert(HintMarks { path, push });
+    }
+
+    pub fn show_message(&mut self, text: impl Into<String>) {
+        self.specs.write_resource::<Messages>().show(text);
+    }
+
+    /// Messages for the player that are still up, oldest first.
+    pub fn messages(&self) -> Vec<Message> {
+        self.specs.read_resource::<Messages>().messages.clone()
+    }
+
+    /// Whether everything has finished sliding and warping.
+    pub fn is_settled(&self) -> bool {
+        let positions = self.specs.read_storage::<Position>();
+        let warps = self.specs.read_storage::<Warp>();
+        positions.join().all(|p| p.offset == Vec2::default()) && warps.join().next().is_none()
+    }
+
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.specs.insert(rules);
+    }
+
+    /// Sets the render layer order, top first, for entities spawned after.
+    pub fn set_layers(&mut self, layers: &[String]) {
+        self.layers = layers.to_vec();
     }
 
     pub fn spawn_wall(&mut self, sprite: &str, x: i32, y: i32, part_pos: Vec2, part_size: Vec2) {
         let texture = self.get_sprite(sprite);
+        let layer = self.layer("Collisions", false);
 
         self.specs
             .create_entity()
             .with(Position {
                 x,
                 y,
-                z: 2,
                 offset: Vec2::default(),
                 direction: Direction::Right,
             })
+            .with(layer)
             .with(Sprite {
                 texture,
                 part_pos,
@@ -132,16 +372,17 @@ impl World {
 
     pub fn spawn_floor(&mut self, sprite: &str, x: i32, y: i32, part_pos: Vec2, part_size: Vec2) {
         let texture = self.get_sprite(sprite);
+        let layer = self.layer("Background", false);
 
         self.specs
             .create_entity()
             .with(Position {
                 x,
                 y,
-                z: 3,
                 offset: Vec2::default(),
                 direction: Direction::Right,
             })
+            .with(layer)
             .with(Sprite {
                 texture,
                 part_pos,
@@ -150,39 +391,73 @@ impl World {
             .build();
     }
 
-    pub fn spawn_box(&mut self, x: i32, y: i32) {
+    pub fn spawn_box(&mut self, x: i32, y: i32, color: Option<Color>) -> Entity {
         let texture = self.get_sprite("box.png");
+        let layer = self.layer("Entities", true);
 
-        self.specs
+        let builder = self
+            .specs
             .create_entity()
             .with(Position {
                 x,
                 y,
-                z: 1,
                 offset: Vec2::default(),
                 direction: Direction::Right,
             })
+            .with(layer)
             .with(Sprite {
                 texture,
                 part_pos: Vec2::new(0.0, 0.0),
                 part_size: Vec2::new(16.0, 16.0),
             })
-            .with(Movable)
-            .build();
+            .with(Movable);
+
+        match color {
+            Some(c) => builder.with(BoxColor(c)).build(),
+            None => builder.build(),
+        }
+    }
+
+    pub fn spawn_goal(&mut self, x: i32, y: i32, color: Option<Color>) -> Entity {
+        let texture = self.get_sprite("goal.png");
+        let layer = self.layer("Entities", false);
+
+        let builder = self
+            .specs
+            .create_entity()
+            .with(Position {
+                x,
+                y,
+                offset: Vec2::default(),
+                direction: Direction::Right,
+            })
+            .with(layer)
+            .with(Sprite {
+                texture,
+                part_pos: Vec2::new(0.0, 0.0),
+                part_size: Vec2::new(16.0, 16.0),
+            })
+            .with(Goal);
+
+        match color {
+            Some(c) => builder.with(GoalColor(c)).build(),
+            None => builder.build(),
+        }
     }
 
-    pub fn spawn_player(&mut self, x: i32, y: i32) {
+    pub fn spawn_player(&mut self, x: i32, y: i32) -> Entity {
         let texture = self.get_sprite("player.png");
+        let layer = self.layer("Entities", true);
 
         self.specs
             .create_entity()
             .with(Position {
                 x,
                 y,
-                z: 1,
                 offset: Vec2::default(),
                 direction: Direction::Down,
             })
+            .with(layer)
             .with(Sprite {
                 texture,
                 part_pos: Vec2::new(0.0, 0.0),
@@ -190,6 +465,7 @@ impl World {
             })
             .with(Animations {
                 size: Vec2::new(6.0, 6.0),
+                frame_size: Vec2::new(16.0, 16.0),
                 time: 0.0,
                 current_animation: "idle-down".to_string(),
                 animations: map! (
@@ -232,10 +508,183 @@ impl World {
                 ),
             })
             .with(Player)
-            .build();
+            .build()
     }
 
-    fn get_sprite(&self, name: &str) -> Handle<Texture> {
-        self.sprites.get(name).expect("bad sprite").clone()
+    pub fn spawn_plate(&mut self, x: i32, y: i32, mode: PlateMode) -> Entity {
+        let texture = self.get_sprite("plate.png");
+        let layer = self.layer("Entities", false);
+        let up_part = Vec2::new(0.0, 0.0);
+        let down_part = Vec2::new(16.0, 0.0);
+
+        self.specs
+            .create_entity()
+            .with(Position {
+                x,
+                y,
+                offset: Vec2::default(),
+                direction: Direction::Right,
+            })
+            .with(layer)
+            .with(Sprite {
+                texture,
+                part_pos: up_part,
+                part_size: Vec2::new(16.0, 16.0),
+            })
+            .with(PressurePlate {
+                mode,
+                doors: vec![],
+                pressed: false,
+                active: false,
+                up_part,
+                down_part,
+            })
+            .build()
+    }
+
+    pub fn spawn_door(&mut self, x: i32, y: i32, open: bool) -> Entity {
+        let texture = self.get_sprite("door.png");
+        let layer = self.layer("Entities", false);
+        let closed_part = Vec2::new(0.0, 0.0);
+        let open_part = Vec2::new(16.0, 0.0);
+
+        let builder = self
+            .specs
+            .create_entity()
+            .with(Position {
+                x,
+                y,
+                offset: Vec2::default(),
+                direction: Direction::Right,
+            })
+            .with(layer)
+            .with(Sprite {
+                texture,
+                part_pos: if open { open_part } else { closed_part },
+                part_size: Vec2::new(16.0, 16.0),
+            })
+            .with(Door {
+                open,
+                default_open: open,
+                closed_part,
+                open_part,
+            });
+
+        if open {
+            builder.build()
+        } else {
+            builder.with(Immovable).build()
+        }
     }
+
+    pub fn spawn_teleporter(&mut self, x: i32, y: i32) -> Entity {
+        let texture = self.get_sprite("teleporter.png");
+        let layer = self.layer("Entities", false);
+
+        self.specs
+            .create_entity()
+            .with(Position {
+                x,
+                y,
+                offset: Vec2::default(),
+                direction: Direction::Right,
+            })
+            .with(layer)
+            .with(Sprite {
+                texture,
+                part_pos: Vec2::new(0.0, 0.0),
+                part_size: Vec2::new(16.0, 16.0),
+            })
+            .with(Teleporter { target: None })
+            .build()
+    }
+
+    pub fn spawn_oneway(&mut self, x: i32, y: i32, direction: Direction) -> Entity {
+        let texture = self.get_sprite("oneway.png");
+        let layer = self.layer("Entities", false);
+
+        self.specs
+            .create_entity()
+            .with(Position {
+                x,
+                y,
+                offset: Vec2::default(),
+                direction,
+            })
+            .with(layer)
+            .with(Sprite {
+                texture,
+                part_pos: direction_part(direction),
+                part_size: Vec2::new(16.0, 16.0),
+            })
+            .with(OneWay(direction))
+            .build()
+    }
+
+    pub fn spawn_conveyor(&mut self, x: i32, y: i32, direction: Direction) -> Entity {
+        let texture = self.get_sprite("conveyor.png");
+        let layer = self.layer("Entities", false);
+
+        self.specs
+            .create_entity()
+            .with(Position {
+                x,
+                y,
+                offset: Vec2::default(),
+                direction,
+            })
+            .with(layer)
+            .with(Sprite {
+                texture,
+                part_pos: direction_part(direction),
+                part_size: Vec2::new(16.0, 16.0),
+            })
+            .with(Conveyor(direction))
+            .build()
+    }
+
+    pub fn link_teleporters(&mut self, a: Entity, b: Entity) {
+        let mut teleporters = self.specs.write_storage::<Teleporter>();
+        if let Some(t) = teleporters.get_mut(a) {
+            t.target = Some(b);
+        }
+        if let Some(t) = teleporters.get_mut(b) {
+            t.target = Some(a);
+        }
+    }
+
+    pub fn link_plate(&mut self, plate: Entity, doors: Vec<Entity>) {
+        let mut plates = self.specs.write_storage::<PressurePlate>();
+        if let Some(p) = plates.get_mut(plate) {
+            p.doors = doors;
+        }
+    }
+
+    fn layer(&self, name: &str, y_sort: bool) -> Layer {
+        // layers missing from the level go below the rest
+        let index = self
+            .layers
+            .iter()
+            .position(|l| l == name)
+            .unwrap_or(self.layers.len());
+        Layer {
+            index: index as u32,
+            y_sort,
+        }
+    }
+
+    fn get_sprite(&self, name: &str) -> String {
+        assert!(render::SPRITES.contains(&name), "bad sprite");
+        name.to_string()
+    }
+}
+
+fn direction_part(direction: Direction) -> Vec2 {
+    let frame = match direction {
+        Direction::Up => 0.0,
+        Direction::Down => 1.0,
+        Direction::Left => 2.0,
+        Direction::Right => 3.0,
+    };
+    Vec2::new(frame * 16.0, 0.0)
 }
@@ -0,0 +1,321 @@
+//! Reading and writing levels in the plain text XSB format.
+//!
+//! Colored boxes are written as uppercase letters and colored goals as
+//! lowercase ones: `r` red, `g` green, `u` blue and `y` yellow. Blue uses `u`
+//! since `b` already stands for a box.
+//!
+//! Standard XSB has no characters for things standing on colored goals, so
+//! this format adds digits in the same color order: `1` to `4` are a box on
+//! a goal of its own color, `5` to `8` the player on a colored goal. Boxes
+//! on a goal of another color cannot be stored.
+
+use duku::Vec2;
+use std::collections::HashMap;
+
+use crate::components::Color;
+use crate::components::Direction;
+use crate::error::Result;
+use crate::level;
+use crate::level::Instance;
+use crate::level::InstanceKind;
+use crate::level::Level;
+use crate::level::Tile;
+use crate::level::TileLayer;
+use crate::resources::Rules;
+use crate::solver::Move;
+
+const BOARD_CHARS: &str = "#@+$*. -_pPbBrRgGuUyY12345678";
+
+// order of the colors in digits
+const COLORS: [Color; 4] = [Color::Red, Color::Green, Color::Blue, Color::Yellow];
+
+pub fn write(level: &Level) -> Result<String> {
+    let walls: Vec<_> = level
+        .tiles
+        .iter()
+        .filter(|t| t.layer == TileLayer::Collisions)
+        .map(|t| (t.x, t.y))
+        .collect();
+
+    let mut cells: HashMap<_, Vec<&InstanceKind>> = HashMap::new();
+    for instance in &level.instances {
+        cells
+            .entry((instance.x, instance.y))
+            .or_default()
+            .push(&instance.kind);
+    }
+
+    let mut text = String::new();
+    for y in (1..=level.height).rev() {
+        let mut row = String::new();
+        for x in 0..level.width {
+            let c = if walls.contains(&(x, y)) {
+                '#'
+            } else {
+                cell_char(cells.get(&(x, y)).map(|c| &c[..]).unwrap_or(&[]))?
+            };
+            row.push(c);
+        }
+        text.push_str(row.trim_end());
+        text.push('\n');
+    }
+    text.push_str(&format!("Title: {}\n", level.name));
+
+    Ok(text)
+}
+
+/// Writes moves in LURD notation, uppercase letters being pushes.
+pub fn write_moves(moves: &[Move]) -> String {
+    moves
+        .iter()
+        .map(|m| {
+            let c = match m.direction {
+                Direction::Up => 'u',
+                Direction::Down => 'd',
+                Direction::Left => 'l',
+                Direction::Right => 'r',
+            };
+            if m.push {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Reads moves in LURD notation. Pushes happen on their own, so case
+/// does not matter.
+pub fn parse_moves(text: &str) -> Result<Vec<Direction>> {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c.to_ascii_lowercase() {
+            'u' => Ok(Direction::Up),
+            'd' => Ok(Direction::Down),
+            'l' => Ok(Direction::Left),
+            'r' => Ok(Direction::Right),
+            _ => Err("invalid move".into()),
+        })
+        .collect()
+}
+
+/// Reads every level, going on past levels that cannot be read. Each
+/// level comes with its name, for reporting.
+pub fn read_each(text: &str) -> Vec<(String, Result<Level>)> {
+    let mut levels: Vec<(String, Result<Level>)> = vec![];
+    let mut rows: Vec<&str> = vec![];
+    let mut title = None;
+    let mut last_titled = true;
+
+    for line in text.lines().chain(Some("")) {
+        let is_board = line.contains('#') && line.chars().all(|c| BOARD_CHARS.contains(c));
+
+        if is_board {
+            rows.push(line);
+            continue;
+        }
+
+        // anything else ends the current board
+        if !rows.is_empty() {
+            last_titled = title.is_some();
+            let name = title
+                .take()
+                .unwrap_or_else(|| format!("Level{}", levels.len() + 1));
+            levels.push((name.clone(), to_level(name, &rows)));
+            rows.clear();
+        }
+
+        // titles can come before or after their board
+        let line = line.trim();
+        if let Some(t) = line.strip_prefix("Title:") {
+            match levels.last_mut() {
+                Some((name, level)) if !last_titled => {
+                    *name = t.trim().to_string();
+                    if let Ok(level) = level {
+                        level.name = name.clone();
+                    }
+                    last_titled = true;
+                }
+                _ => title = Some(t.trim().to_string()),
+            }
+        } else if !line.is_empty() && !line.starts_with(';') && !line.contains(':') {
+            title = title.or_else(|| Some(line.to_string()));
+        }
+    }
+
+    levels
+}
+
+fn to_level(name: String, rows: &[&str]) -> Result<Level> {
+    let height = rows.len() as i32;
+    let width = rows.iter().map(|r| r.chars().count()).max().unwrap_or(0) as i32;
+
+    let mut tiles = vec![];
+    let mut instances = vec![];
+    for (r, row) in rows.iter().enumerate() {
+        let y = height - r as i32;
+        let mut chars = row.chars();
+
+        for x in 0..width {
+            let c = chars.next().unwrap_or(' ');
+
+            tiles.push(Tile {
+                layer: TileLayer::Background,
+                x,
+                y,
+                tileset: "floor.png".to_string(),
+                part_pos: Vec2::new(0.0, 16.0),
+            });
+
+            let kinds = match c {
+                '#' => {
+                    tiles.push(Tile {
+                        layer: TileLayer::Collisions,
+                        x,
+                        y,
+                        tileset: "wall.png".to_string(),
+                        part_pos: Vec2::new(32.0, 48.0),
+                    });
+                    vec![]
+                }
+                '@' | 'p' => vec![InstanceKind::Player],
+                '+' | 'P' => vec![InstanceKind::Player, InstanceKind::Goal(None)],
+                '$' | 'b' => vec![InstanceKind::Box(None)],
+                '*' | 'B' => vec![InstanceKind::Box(None), InstanceKind::Goal(None)],
+                '.' => vec![InstanceKind::Goal(None)],
+                ' ' | '-' | '_' => vec![],
+                '1'..='4' => {
+                    let color = Some(COLORS[c as usize - '1' as usize]);
+                    vec![InstanceKind::Box(color), InstanceKind::Goal(color)]
+                }
+                '5'..='8' => {
+                    let color = Some(COLORS[c as usize - '5' as usize]);
+                    vec![InstanceKind::Player, InstanceKind::Goal(color)]
+                }
+                c => match color(c) {
+                    Some((color, true)) => vec![InstanceKind::Box(Some(color))],
+                    Some((color, false)) => vec![InstanceKind::Goal(Some(color))],
+                    None => return Err("invalid xsb character".into()),
+                },
+            };
+
+            for kind in kinds {
+                instances.push(Instance {
+                    iid: None,
+                    kind,
+                    x,
+                    y,
+                });
+            }
+        }
+    }
+
+    Ok(Level {
+        name,
+        width,
+        height,
+        grid_size: 16,
+        rules: Rules::default(),
+        music: None,
+        tiles,
+        instances,
+        layers: level::default_layers(),
+        unknown: vec![],
+    })
+}
+
+fn cell_char(kinds: &[&InstanceKind]) -> Result<char> {
+    let mut player = false;
+    let mut goal = None;
+    let mut boxed = None;
+    for kind in kinds {
+        match kind {
+            InstanceKind::Player => player = true,
+            InstanceKind::Goal(color) => goal = Some(*color),
+            InstanceKind::Box(color) => boxed = Some(*color),
+            _ => return Err("level uses things xsb cannot store".into()),
+        }
+    }
+
+    match (player, boxed, goal) {
+        (true, None, None) => Ok('@'),
+        (true, None, Some(None)) => Ok('+'),
+        (false, Some(None), None) => Ok('$'),
+        (false, Some(None), Some(None)) => Ok('*'),
+        (false, None, Some(None)) => Ok('.'),
+        (false, None, None) => Ok(' '),
+        (false, Some(Some(c)), None) => Ok(color_char(c, true)),
+        (false, None, Some(Some(c))) => Ok(color_char(c, false)),
+        (false, Some(Some(b)), Some(Some(g))) if b == g => Ok(color_digit(b, false)),
+        (true, None, Some(Some(c))) => Ok(color_digit(c, true)),
+        _ => Err("xsb cannot store this tile".into()),
+    }
+}
+
+fn color(c: char) -> Option<(Color, bool)> {
+    let color = match c.to_ascii_lowercase() {
+        'r' => Color::Red,
+        'g' => Color::Green,
+        'u' => Color::Blue,
+        'y' => Color::Yellow,
+        _ => return None,
+    };
+    Some((color, c.is_ascii_uppercase()))
+}
+
+fn color_char(color: Color, is_box: bool) -> char {
+    let c = match color {
+        Color::Red => 'r',
+        Color::Green => 'g',
+        Color::Blue => 'u',
+        Color::Yellow => 'y',
+    };
+    if is_box {
+        c.to_ascii_uppercase()
+    } else {
+        c
+    }
+}
+
+fn color_digit(color: Color, player: bool) -> char {
+    let index = match color {
+        Color::Red => 0,
+        Color::Green => 1,
+        Color::Blue => 2,
+        Color::Yellow => 3,
+    };
+    let first = if player { b'5' } else { b'1' };
+    (first + index) as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colored_round_trip() {
+        let boards = [
+            "#######\n#@1 Rr#\n# $.Uu#\n#######\nTitle: Boxes\n",
+            "#######\n#6 G 3#\n#######\nTitle: Player\n",
+        ];
+        for text in boards.iter() {
+            let levels = read_each(text);
+            assert_eq!(levels.len(), 1);
+            let level = levels[0].1.as_ref().unwrap();
+            assert!(crate::validate::validate(level).is_empty());
+            assert_eq!(write(level).unwrap(), *text);
+        }
+    }
+
+    #[test]
+    fn mismatched_colors() {
+        let mut level = read_each("####\n#@R#\n####\n").remove(0).1.unwrap();
+        level.instances.push(Instance {
+            iid: None,
+            kind: InstanceKind::Goal(Some(Color::Blue)),
+            x: 2,
+            y: 2,
+        });
+        assert!(write(&level).is_err());
+    }
+}